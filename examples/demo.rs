@@ -21,6 +21,7 @@ use libremarkable::framebuffer::common::*;
 use libremarkable::appctx;
 use libremarkable::ui_extensions::element::{UIConstraintRefresh, UIElement, UIElementWrapper};
 
+use libremarkable::framebuffer::offscreen::Rotation;
 use libremarkable::framebuffer::refresh::PartialRefreshMode;
 use libremarkable::framebuffer::{FramebufferDraw, FramebufferRefresh};
 
@@ -43,6 +44,8 @@ fn loop_update_topbar(
             ref mut text,
             scale: _,
             foreground: _,
+            max_width: _,
+            font: _,
         } = time_label.write().unwrap().inner
         {
             *text = format!("{}", dt.format("%F %r"));
@@ -51,6 +54,8 @@ fn loop_update_topbar(
             ref mut text,
             scale: _,
             foreground: _,
+            max_width: _,
+            font: _,
         } = battery_label.write().unwrap().inner
         {
             *text = format!(
@@ -90,14 +95,16 @@ fn on_wacom_input(app: &mut appctx::ApplicationContext, input: wacom::WacomEvent
                     rad.ceil() as usize,
                     color::BLACK,
                 );
-                framebuffer.partial_refresh(
+                if let Err(e) = framebuffer.partial_refresh(
                     &rect,
                     PartialRefreshMode::Async,
                     waveform_mode::WAVEFORM_MODE_DU,
                     display_temp::TEMP_USE_REMARKABLE_DRAW,
                     dither_mode::EPDC_FLAG_EXP1,
                     DRAWING_QUANT_BIT,
-                );
+                ) {
+                    warn!("Failed to refresh a stroke: {0}", e);
+                }
             }
             *prev = (y as i32, x as i32);
         }
@@ -141,17 +148,23 @@ fn on_touch_handler(app: &mut appctx::ApplicationContext, input: multitouch::Mul
                     ((x + 200) as f32, (y + 200) as f32),
                     color::BLACK,
                 ),
-                2 => framebuffer.draw_circle(y as usize, x as usize, 20, color::BLACK),
+                2 => framebuffer.draw_circle(
+                    libremarkable::cgmath::Point2::new(x as i32, y as i32),
+                    20,
+                    color::BLACK,
+                ),
                 _ => return,
             };
-            framebuffer.partial_refresh(
+            if let Err(e) = framebuffer.partial_refresh(
                 &rect,
                 PartialRefreshMode::Async,
                 waveform_mode::WAVEFORM_MODE_DU,
                 display_temp::TEMP_USE_REMARKABLE_DRAW,
                 dither_mode::EPDC_FLAG_USE_DITHERING_ALPHA,
                 DRAWING_QUANT_BIT,
-            );
+            ) {
+                warn!("Failed to refresh a shape: {0}", e);
+            }
         }
         _ => {}
     }
@@ -262,14 +275,16 @@ fn on_touch_rustlogo(
     };
 
     let rect = framebuffer.draw_text(240, 1140, format!("{0}", new_press_count), 65, color::BLACK);
-    framebuffer.partial_refresh(
+    if let Err(e) = framebuffer.partial_refresh(
         &rect,
         PartialRefreshMode::Wait,
         waveform,
         display_temp::TEMP_USE_MAX,
         dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
         0,
-    );
+    ) {
+        warn!("Failed to refresh the press count: {0}", e);
+    }
 }
 
 lazy_static! {
@@ -308,6 +323,8 @@ fn main() {
             onclick: Some(on_touch_rustlogo),
             inner: UIElement::Image {
                 img: image::load_from_memory(include_bytes!("../assets/rustlang.bmp")).unwrap(),
+                scale: 1.0,
+                rotation: Rotation::None,
             },
             ..Default::default()
         })),
@@ -324,6 +341,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "[TOUCH TO EXIT TO REMARKABLE]".to_owned(),
                 scale: 35,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -338,6 +357,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Available at:".to_owned(),
                 scale: 70,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -352,6 +373,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "github.com/canselcik/libremarkable".to_owned(),
                 scale: 60,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -366,6 +389,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Low Latency eInk Display Partial Refresh API".to_owned(),
                 scale: 45,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -380,6 +405,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Capacitive Multitouch Input Support".to_owned(),
                 scale: 45,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -394,6 +421,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Physical Button Support".to_owned(),
                 scale: 45,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -408,6 +437,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Wacom Digitizer Support".to_owned(),
                 scale: 45,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -423,6 +454,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Toggle Touch".to_owned(),
                 scale: 50,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -437,6 +470,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Redraw Layout".to_owned(),
                 scale: 50,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -451,6 +486,8 @@ fn main() {
                 foreground: color::BLACK,
                 text: "Quick Redraw".to_owned(), // maybe quick redraw for the demo or waveform change?
                 scale: 50,
+                max_width: None,
+                font: None,
             },
             ..Default::default()
         })),
@@ -466,6 +503,8 @@ fn main() {
             foreground: color::BLACK,
             text: format!("{}", dt.format("%F %r")),
             scale: 75,
+            max_width: None,
+            font: None,
         },
         ..Default::default()
     }));
@@ -484,6 +523,8 @@ fn main() {
                 )
             ),
             scale: 44,
+            max_width: None,
+            font: None,
         },
         ..Default::default()
     }));
@@ -1,12 +1,16 @@
 use std;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{mpsc, Arc, RwLock};
 use std::cell::UnsafeCell;
 use std::ops::DerefMut;
+use std::time::{Duration, Instant};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
+use cgmath;
 use image;
+use image::GenericImage;
+use rusttype::Scale;
 
 use input;
 use input::ev;
@@ -18,17 +22,22 @@ use rb::RbConsumer;
 use framebuffer::common::*;
 
 use ui_extensions::luaext;
-use ui_extensions::element::{ActiveRegionFunction, ActiveRegionHandler, UIConstraintRefresh,
-                             UIElementWrapper};
+use ui_extensions::element::{ActiveRegionFunction, ActiveRegionHandler, ProgressBar,
+                             UIConstraintRefresh, UIElement, UIElementWrapper};
 use hlua;
 use hlua::Lua;
 
 use aabb_quadtree::{geom, ItemId, QuadTree};
 
+use error::Error;
 use framebuffer::core;
+use framebuffer::draw::{is_bilevel_image, transform_image, DitherAlgorithm, FontId,
+                        ImageAdjust};
+use framebuffer::offscreen::Rotation;
 use framebuffer::refresh::PartialRefreshMode;
 use framebuffer::FramebufferBase;
 use framebuffer::FramebufferDraw;
+use framebuffer::FramebufferIO;
 use framebuffer::FramebufferRefresh;
 
 use input::InputEvent;
@@ -36,6 +45,64 @@ use input::wacom::WacomEvent;
 use input::gpio::GPIOEvent;
 use input::multitouch::MultitouchEvent;
 
+/// Number of black/white inversion cycles `warm_up` goes through
+const WARM_UP_CYCLES: usize = 2;
+
+const TOAST_TEXT_SCALE: usize = 40;
+const TOAST_PADDING_PX: usize = 20;
+const TOAST_BOTTOM_MARGIN_PX: usize = 120;
+/// How often `dispatch_events` checks for input while a toast waits to be removed
+const TOAST_POLL_INTERVAL_MS: u64 = 20;
+
+/// Bitmap drawn at the pen position along with the screen contents it currently covers
+struct PenCursor {
+    bitmap: image::GrayImage,
+    visible: bool,
+    shown: Option<(mxcfb_rect, Vec<u8>)>,
+}
+
+/// Taps on an active region within this long of the previous one are ignored by default
+const DEFAULT_DEBOUNCE_MS: u64 = 250;
+
+/// Thickness of the border drawn around buttons by `display_button`
+const BUTTON_BORDER_PX: usize = 2;
+
+/// Direction in which the new image moves during `ApplicationContext::transition_slide`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SlideDirection {
+    /// Enters from the right edge
+    Left,
+    /// Enters from the left edge
+    Right,
+    /// Enters from the bottom edge
+    Up,
+    /// Enters from the top edge
+    Down,
+}
+
+/// How `ApplicationContext::display_text_ex` laid a text out
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextLayout {
+    /// Area covered by all lines
+    pub bounds: mxcfb_rect,
+    pub lines: usize,
+    /// Baseline a line height below the last line
+    pub next_y: usize,
+}
+
+/// Toasts waiting to be shown along with the one on screen, see `update_toasts`
+struct ToastQueue {
+    pending: VecDeque<(String, Duration)>,
+    shown: Option<ShownToast>,
+}
+
+/// Area of a toast on screen, the content it covers and when it is to be removed
+struct ShownToast {
+    pill: mxcfb_rect,
+    background: Vec<u8>,
+    until: Instant,
+}
+
 unsafe impl<'a> Send for ApplicationContext<'a> {}
 unsafe impl<'a> Sync for ApplicationContext<'a> {}
 
@@ -48,6 +115,16 @@ pub struct ApplicationContext<'a> {
     on_touch: fn(&mut ApplicationContext, MultitouchEvent),
     active_regions: QuadTree<ActiveRegionHandler>,
     ui_elements: HashMap<String, Arc<RwLock<UIElementWrapper>>>,
+    toasts: ToastQueue,
+    cursor: Option<PenCursor>,
+    /// Input threads started by `poll_input` and the channel they all send to
+    polled_input: Option<(ev::EvDevContext, mpsc::Receiver<InputEvent>)>,
+    /// Taps on an active region within this long of its last accepted tap are ignored
+    debounce_duration: Duration,
+    /// Shortest time between two `present` calls, `None` when they aren't paced
+    min_frame_interval: Option<Duration>,
+    /// When `present` last committed a frame
+    last_present: Option<Instant>,
     yres: u32,
     xres: u32,
 }
@@ -81,7 +158,38 @@ impl<'a> ApplicationContext<'a> {
         on_wacom: fn(&mut ApplicationContext, WacomEvent),
         on_touch: fn(&mut ApplicationContext, MultitouchEvent),
     ) -> ApplicationContext<'static> {
-        let framebuffer = Box::new(core::Framebuffer::new("/dev/fb0"));
+        ApplicationContext::with_framebuffer(
+            core::Framebuffer::new("/dev/fb0"),
+            on_button,
+            on_wacom,
+            on_touch,
+        )
+    }
+
+    /// Same as `new` but draws to a `core::Framebuffer::in_memory` of `yres` by `xres`, so
+    /// that UI code can be tested off-device
+    pub fn in_memory(
+        yres: u32,
+        xres: u32,
+        on_button: fn(&mut ApplicationContext, GPIOEvent),
+        on_wacom: fn(&mut ApplicationContext, WacomEvent),
+        on_touch: fn(&mut ApplicationContext, MultitouchEvent),
+    ) -> ApplicationContext<'static> {
+        ApplicationContext::with_framebuffer(
+            core::Framebuffer::in_memory(yres, xres),
+            on_button,
+            on_wacom,
+            on_touch,
+        )
+    }
+
+    fn with_framebuffer(
+        framebuffer: core::Framebuffer<'static>,
+        on_button: fn(&mut ApplicationContext, GPIOEvent),
+        on_wacom: fn(&mut ApplicationContext, WacomEvent),
+        on_touch: fn(&mut ApplicationContext, MultitouchEvent),
+    ) -> ApplicationContext<'static> {
+        let framebuffer = Box::new(framebuffer);
         let yres = framebuffer.var_screen_info.yres;
         let xres = framebuffer.var_screen_info.xres;
         let mut res = ApplicationContext {
@@ -94,6 +202,15 @@ impl<'a> ApplicationContext<'a> {
             on_wacom,
             on_touch,
             ui_elements: HashMap::new(),
+            toasts: ToastQueue {
+                pending: VecDeque::new(),
+                shown: None,
+            },
+            cursor: None,
+            polled_input: None,
+            debounce_duration: Duration::from_millis(DEFAULT_DEBOUNCE_MS),
+            min_frame_interval: None,
+            last_present: None,
             active_regions: QuadTree::default(geom::Rect::from_points(
                 &geom::Point { x: 0.0, y: 0.0 },
                 &geom::Point {
@@ -145,28 +262,225 @@ impl<'a> ApplicationContext<'a> {
         text: String,
         refresh: UIConstraintRefresh,
     ) -> mxcfb_rect {
+        self.display_text_font(y, x, c, scale, &text, None, refresh)
+    }
+
+    /// Same as `display_text` but with `font`, see `load_font`
+    pub fn display_text_font(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: color,
+        scale: usize,
+        text: &str,
+        font: Option<FontId>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        self.display_text_ex(y, x, c, scale, text, None, font, refresh).bounds
+    }
+
+    /// Same as `display_text_wrapped` when given a `max_width` and `display_text_font`
+    /// otherwise, but also returns the number of lines laid out and the baseline right below
+    /// the last one, where the next paragraph can be drawn without measuring this one
+    pub fn display_text_ex(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: color,
+        scale: usize,
+        text: &str,
+        max_width: Option<usize>,
+        font: Option<FontId>,
+        refresh: UIConstraintRefresh,
+    ) -> TextLayout {
         let framebuffer = self.get_framebuffer_ref();
-        let draw_area: mxcfb_rect = framebuffer.draw_text(y, x, text, scale, c);
-        let marker = match refresh {
-            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                ),
-            _ => return draw_area,
+        let line_height = framebuffer.line_height_font(scale, font);
+        let lines = match max_width {
+            Some(max_width) => framebuffer.wrap_text_font(text, scale, max_width, font),
+            None => vec![text.to_owned()],
         };
+        let mut draw_area: Option<mxcfb_rect> = None;
+        for (i, line) in lines.iter().enumerate() {
+            let rect = framebuffer.draw_text_font(y + i * line_height, x, line, scale, c, font);
+            if rect.width > 0 && rect.height > 0 {
+                draw_area = Some(draw_area.map_or(rect, |d| d.union(&rect)));
+            }
+        }
+        TextLayout {
+            bounds: match draw_area {
+                Some(area) => self.refresh_drawn_area(area, refresh),
+                None => mxcfb_rect::invalid(),
+            },
+            lines: lines.len(),
+            next_y: y + lines.len() * line_height,
+        }
+    }
 
-        match refresh {
-            UIConstraintRefresh::RefreshAndWait => {
-                framebuffer.wait_refresh_complete(marker);
+    /// Clears a box of `height` by `width` at `(y, x)` and draws the `lines` that fall within
+    /// it once scrolled down by `scroll` pixels, one line height apart. Lines cut by the top
+    /// or bottom edge are clipped to the box. Returns the rect of the box whatever is shown,
+    /// so that its refresh doesn't change as the content scrolls.
+    pub fn display_text_viewport(
+        &mut self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        lines: &[String],
+        scale: usize,
+        scroll: usize,
+        c: color,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let rect = mxcfb_rect {
+            top: y as u32,
+            left: x as u32,
+            height: height as u32,
+            width: width as u32,
+        };
+        framebuffer.fill_rect(y, x, height, width, color::WHITE);
+
+        let line_height = framebuffer.line_height(scale);
+        if line_height > 0 {
+            let ascent = framebuffer
+                .default_font
+                .v_metrics(Scale::uniform(scale as f32))
+                .ascent
+                .ceil() as usize;
+            // Clipped with a viewport within the current one, which is put back afterwards
+            let outer = framebuffer.viewport();
+            let clip = match outer {
+                Some(outer) => outer.intersection(&rect).unwrap_or_else(mxcfb_rect::invalid),
+                None => rect,
+            };
+            framebuffer.set_viewport(Some(clip));
+            let first = scroll / line_height;
+            let end = std::cmp::min((scroll + height) / line_height + 1, lines.len());
+            for (i, line) in lines.iter().enumerate().take(end).skip(first) {
+                if let Some(baseline) = (y + ascent + i * line_height).checked_sub(scroll) {
+                    framebuffer.draw_text(baseline, x, line.to_owned(), scale, c);
+                }
             }
-            _ => {}
+            framebuffer.set_viewport(outer);
+        }
+        self.refresh_drawn_area(rect, refresh)
+    }
+
+    /// Loads a TrueType font that text can then be drawn with, e.g. by setting the `font`
+    /// of a `UIElement::Text`. Fails if `bytes` isn't a font.
+    pub fn load_font(&mut self, bytes: &[u8]) -> Result<FontId, Error> {
+        self.get_framebuffer_ref().load_font(bytes)
+    }
+
+    /// Same as `display_text_font` but word-wraps `text` to `max_width`, see
+    /// `FramebufferDraw::wrap_text`. `y` is the baseline of the first line and every other
+    /// line is a line height below the previous one. Returns the area covered by all lines.
+    pub fn display_text_wrapped(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: color,
+        scale: usize,
+        text: &str,
+        max_width: usize,
+        font: Option<FontId>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        self.display_text_ex(y, x, c, scale, text, Some(max_width), font, refresh).bounds
+    }
+
+    /// Same as `display_text` but also returns the position right after the last glyph, with
+    /// `y` still being the baseline. Passing it back as the `(y, x)` of the next call draws
+    /// the next run of text, e.g. in another color, on the same line right after this one.
+    pub fn display_text_run(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: color,
+        scale: usize,
+        text: String,
+        refresh: UIConstraintRefresh,
+    ) -> (mxcfb_rect, cgmath::Point2<usize>) {
+        let end_x = match self.get_framebuffer_ref()
+            .measure_glyphs(y, x, &text, scale)
+            .last()
+        {
+            Some(last) => (last.left + last.width) as usize,
+            None => x,
         };
-        return draw_area;
+        let rect = self.display_text(y, x, c, scale, text, refresh);
+        (rect, cgmath::Point2 { x: end_x, y })
+    }
+
+    /// Same as `display_text` but first fills the background behind the characters of each
+    /// `(start_char, end_char, color)` span of `highlights`, `end_char` being exclusive. Where
+    /// spans overlap, the one that comes last in `highlights` wins. Returns the area covered
+    /// by both the text and the highlights.
+    pub fn display_text_highlighted(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: color,
+        scale: usize,
+        text: String,
+        highlights: &[(usize, usize, color)],
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let glyphs = framebuffer.measure_glyphs(y, x, &text, scale);
+
+        // Resolve the highlight of every character first so that overlapping spans turn into
+        // contiguous runs of a single color
+        let mut per_char: Vec<Option<color>> = vec![None; glyphs.len()];
+        for &(start, end, highlight) in highlights {
+            let end = std::cmp::min(end, glyphs.len());
+            for slot in per_char.iter_mut().take(end).skip(start) {
+                *slot = Some(highlight);
+            }
+        }
+
+        let mut covered: Option<mxcfb_rect> = None;
+        let mut i = 0;
+        while i < glyphs.len() {
+            let highlight = match per_char[i] {
+                Some(highlight) => highlight,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let mut end = i + 1;
+            while end < glyphs.len() && per_char[end] == Some(highlight) {
+                end += 1;
+            }
+            let (first, last) = (glyphs[i], glyphs[end - 1]);
+            let run = mxcfb_rect {
+                top: first.top,
+                left: first.left,
+                height: first.height,
+                width: (last.left + last.width).saturating_sub(first.left),
+            };
+            framebuffer.fill_rect(
+                run.top as usize,
+                run.left as usize,
+                run.height as usize,
+                run.width as usize,
+                highlight,
+            );
+            covered = Some(match covered {
+                Some(r) => r.union(&run),
+                None => run,
+            });
+            i = end;
+        }
+
+        let text_area = framebuffer.draw_text(y, x, text, scale, c);
+        let draw_area = match covered {
+            Some(r) => r.union(&text_area),
+            None => text_area,
+        };
+        self.refresh_drawn_area(draw_area, refresh)
     }
 
     pub fn display_image(
@@ -178,32 +492,613 @@ impl<'a> ApplicationContext<'a> {
     ) -> mxcfb_rect {
         let framebuffer = self.get_framebuffer_ref();
         let draw_area = framebuffer.draw_grayscale_image(&img, y, x);
-        let marker = match refresh {
-            UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                ),
-            _ => return draw_area,
+        self.refresh_drawn_area(draw_area, refresh)
+    }
+
+    /// Same as `display_image` but blends `img` with what is under it by its alpha channel
+    /// instead of drawing it opaque, so that transparent icons don't show up in a box
+    pub fn display_image_alpha(
+        &mut self,
+        img: &image::DynamicImage,
+        y: usize,
+        x: usize,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = framebuffer.draw_image_alpha(&img, y, x);
+        self.refresh_drawn_area(draw_area, refresh)
+    }
+
+    /// Same as `display_image` but dithers `img` in software with `algorithm`, which renders
+    /// photos and gradients with far less banding than the EPDC passthrough dithering
+    pub fn display_image_dithered(
+        &mut self,
+        img: &image::DynamicImage,
+        y: usize,
+        x: usize,
+        algorithm: DitherAlgorithm,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = framebuffer.draw_dithered_image(&img, y, x, algorithm);
+        self.refresh_drawn_area(draw_area, refresh)
+    }
+
+    /// Same as `display_image` but draws `img` scaled by `scale` and turned by `rotation`.
+    /// Returns the bounding rect of the transformed image.
+    pub fn display_image_transformed(
+        &mut self,
+        img: &image::DynamicImage,
+        y: usize,
+        x: usize,
+        scale: f32,
+        rotation: Rotation,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let transformed = transform_image(img, scale, rotation);
+        self.display_image(&transformed, y, x, refresh)
+    }
+
+    /// Slides `new_img` into place at `(y, x)` with its leading edge entering from the side
+    /// opposite to `direction`, in `frames` steps that are each refreshed and waited on with
+    /// the monochrome `WAVEFORM_MODE_GLR16`, the quickest waveform available. Fewer frames
+    /// make for a faster but choppier transition. Once in place, whatever is left of `from`,
+    /// typically the rect of the image being replaced, is cleared to white and everything
+    /// is refreshed again with `WAVEFORM_MODE_GC16`. Returns the rect of `new_img`.
+    pub fn transition_slide(
+        &mut self,
+        from: Option<mxcfb_rect>,
+        new_img: &image::DynamicImage,
+        y: usize,
+        x: usize,
+        direction: SlideDirection,
+        frames: u32,
+    ) -> mxcfb_rect {
+        let (width, height) = (new_img.width(), new_img.height());
+        let mut source = new_img.clone();
+        let step = UIConstraintRefresh::RefreshWith {
+            waveform_mode: Some(waveform_mode::WAVEFORM_MODE_GLR16),
+            temperature: Some(display_temp::TEMP_USE_REMARKABLE_DRAW),
+            dither_mode: None,
+            wait: true,
         };
+        for frame in 1..frames {
+            let shown = |len: u32| (len as u64 * frame as u64 / frames as u64) as u32;
+            // Visible part of the image as (crop top, crop left, crop height, crop width),
+            // drawn along the edge it enters from
+            let (top, left, h, w) = match direction {
+                SlideDirection::Left => (0, 0, height, shown(width)),
+                SlideDirection::Right => (0, width - shown(width), height, shown(width)),
+                SlideDirection::Up => (0, 0, shown(height), width),
+                SlideDirection::Down => (height - shown(height), 0, shown(height), width),
+            };
+            if h == 0 || w == 0 {
+                continue;
+            }
+            let (draw_y, draw_x) = match direction {
+                SlideDirection::Left => (y, x + (width - w) as usize),
+                SlideDirection::Up => (y + (height - h) as usize, x),
+                SlideDirection::Right | SlideDirection::Down => (y, x),
+            };
+            let part = source.crop(left, top, w, h);
+            self.display_image(&part, draw_y, draw_x, step.clone());
+        }
+
+        let framebuffer = self.get_framebuffer_ref();
+        if let Some(from) = from {
+            framebuffer.fill_rect(
+                from.top as usize,
+                from.left as usize,
+                from.height as usize,
+                from.width as usize,
+                color::WHITE,
+            );
+        }
+        let rect = framebuffer.draw_grayscale_image(new_img, y, x);
+        let cleanup = from.map_or(rect, |from| from.union(&rect));
+        self.refresh_drawn_area(
+            cleanup,
+            UIConstraintRefresh::RefreshWith {
+                waveform_mode: Some(waveform_mode::WAVEFORM_MODE_GC16),
+                temperature: Some(display_temp::TEMP_USE_AMBIENT),
+                dither_mode: None,
+                wait: false,
+            },
+        );
+        rect
+    }
 
-        match refresh {
-            UIConstraintRefresh::RefreshAndWait => {
-                framebuffer.wait_refresh_complete(marker);
+    /// Draws a box of `height` by `width` at `(y, x)`, filled with `c` when `filled` is set
+    /// and otherwise outlined with `border_px` thick edges drawn inwards
+    pub fn display_rect(
+        &mut self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        border_px: u32,
+        c: color,
+        filled: bool,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = match filled {
+            true => {
+                framebuffer.fill_rect(y, x, height, width, c);
+                mxcfb_rect {
+                    top: y as u32,
+                    left: x as u32,
+                    height: height as u32,
+                    width: width as u32,
+                }
             }
-            _ => {}
+            false => framebuffer.draw_rect(y, x, height, width, border_px as usize, c),
+        };
+        self.refresh_drawn_area(draw_area, refresh)
+    }
+
+    /// Rect and text origin `(y, x)` of a button at `(y, x)` whose `label` is surrounded by
+    /// `padding` pixels on every side
+    fn button_layout(
+        &mut self,
+        y: usize,
+        x: usize,
+        label: &str,
+        scale: usize,
+        padding: usize,
+    ) -> (mxcfb_rect, usize, usize) {
+        let (text, ascent) = self.get_framebuffer_ref().measure_text_origin(label, scale);
+        let rect = mxcfb_rect {
+            top: y as u32,
+            left: x as u32,
+            height: text.height + 2 * padding as u32,
+            width: text.width + 2 * padding as u32,
+        };
+        let baseline = y + padding + ascent;
+        (rect, baseline, x + padding)
+    }
+
+    /// Rect `display_button` would cover if called with the same arguments
+    pub fn button_rect(
+        &mut self,
+        y: usize,
+        x: usize,
+        label: &str,
+        scale: usize,
+        padding: usize,
+    ) -> mxcfb_rect {
+        self.button_layout(y, x, label, scale, padding).0
+    }
+
+    /// Draws `label` in `foreground` centered in a box filled with `background` and bordered
+    /// with `foreground`, sized to fit the label with `padding` pixels around it. The corners
+    /// of the box are rounded off with `corner_radius`, square when 0.
+    pub fn display_button(
+        &mut self,
+        y: usize,
+        x: usize,
+        label: &str,
+        scale: usize,
+        padding: usize,
+        foreground: color,
+        background: color,
+        corner_radius: u32,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let (rect, text_y, text_x) = self.button_layout(y, x, label, scale, padding);
+        let framebuffer = self.get_framebuffer_ref();
+        let (height, width) = (rect.height as usize, rect.width as usize);
+        match corner_radius {
+            0 => {
+                framebuffer.fill_rect(y, x, height, width, background);
+                framebuffer.draw_rect(y, x, height, width, BUTTON_BORDER_PX, foreground);
+            }
+            _ => {
+                // The border is what the background inset by its thickness leaves uncovered
+                let border = BUTTON_BORDER_PX as u32;
+                framebuffer.fill_rounded_rect(
+                    rect.top,
+                    rect.left,
+                    rect.height,
+                    rect.width,
+                    corner_radius,
+                    foreground,
+                );
+                framebuffer.fill_rounded_rect(
+                    rect.top + border,
+                    rect.left + border,
+                    rect.height.saturating_sub(2 * border),
+                    rect.width.saturating_sub(2 * border),
+                    corner_radius.saturating_sub(border),
+                    background,
+                );
+            }
+        }
+        framebuffer.draw_text(text_y, text_x, label.to_owned(), scale, foreground);
+        self.refresh_drawn_area(rect, refresh)
+    }
+
+    /// Draws `bar` at `(y, x)`: a `track` colored box with `border_px` thick edges in `fill`,
+    /// filled with `fill` from the left in proportion to `fraction`, clamped to `[0, 1]`.
+    /// When `previous` is the bar on screen at `(y, x)` and only its `fraction` differs from
+    /// `bar`, only the columns whose color changes are refreshed, otherwise the whole bar is.
+    /// Returns the rect of the whole bar either way.
+    pub fn display_progress_bar(
+        &mut self,
+        y: usize,
+        x: usize,
+        bar: &ProgressBar,
+        previous: Option<&ProgressBar>,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let changes_only = match previous {
+            Some(previous) => ProgressBar {
+                fraction: bar.fraction,
+                ..*previous
+            } == *bar,
+            None => false,
+        };
+        let ProgressBar {
+            height,
+            width,
+            fraction,
+            fill,
+            track,
+            border_px,
+        } = *bar;
+        let framebuffer = self.get_framebuffer_ref();
+        let border = std::cmp::min(border_px as usize, std::cmp::min(height, width) / 2);
+        let (inner_y, inner_x) = (y + border, x + border);
+        let (inner_height, inner_width) = (height - 2 * border, width - 2 * border);
+        let filled = (inner_width as f32 * fraction.max(0.0).min(1.0)).round() as usize;
+
+        // The bar is the same from top to bottom, so one row tells which columns change
+        let changed = match changes_only && inner_height > 0 {
+            true => {
+                let row = inner_y + inner_height / 2;
+                let differs = |col: usize| {
+                    let expected = match col < filled {
+                        true => fill,
+                        false => track,
+                    };
+                    framebuffer.read_pixel(row, inner_x + col).as_native() != expected.as_native()
+                };
+                let first = (0..inner_width).find(|&col| differs(col));
+                let last = (0..inner_width).rev().find(|&col| differs(col));
+                match (first, last) {
+                    (Some(first), Some(last)) => Some(mxcfb_rect {
+                        top: inner_y as u32,
+                        left: (inner_x + first) as u32,
+                        height: inner_height as u32,
+                        width: (last - first + 1) as u32,
+                    }),
+                    _ => None,
+                }
+            }
+            false => None,
         };
+
+        framebuffer.fill_rect(inner_y, inner_x, inner_height, filled, fill);
+        framebuffer.fill_rect(
+            inner_y,
+            inner_x + filled,
+            inner_height,
+            inner_width - filled,
+            track,
+        );
+        let bar = framebuffer.draw_rect(y, x, height, width, border, fill);
+
+        match (changes_only, changed) {
+            (false, _) => self.refresh_drawn_area(bar, refresh),
+            (true, Some(span)) => {
+                self.refresh_drawn_area(span, refresh);
+                bar
+            }
+            (true, None) => bar,
+        }
+    }
+
+    /// Same as `display_image` but takes the thresholding path of `draw_bilevel_image` when
+    /// `img` is nearly bilevel, such as a previously captured screenshot
+    pub fn display_image_bilevel(
+        &mut self,
+        img: &image::DynamicImage,
+        y: usize,
+        x: usize,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        if !is_bilevel_image(img) {
+            return self.display_image(img, y, x, refresh);
+        }
+        let framebuffer = self.get_framebuffer_ref();
+        let draw_area = framebuffer.draw_bilevel_image(&img, y, x);
+        self.refresh_drawn_area(draw_area, refresh)
+    }
+
+    /// Same as `display_image` but applies `adjust` to the luma of `img` before drawing it
+    pub fn display_image_adjusted(
+        &mut self,
+        img: &image::DynamicImage,
+        y: usize,
+        x: usize,
+        adjust: ImageAdjust,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let adjusted = adjust.apply(img);
+        self.display_image(&adjusted, y, x, refresh)
+    }
+
+    /// Runs `draw` on the framebuffer, then refreshes the rect it returns with the defaults
+    /// of `UIConstraintRefresh::RefreshAndWait`. Only returns once the panel has finished
+    /// updating, with the rect `draw` returned.
+    pub fn draw_and_show<F: FnOnce(&mut core::Framebuffer) -> mxcfb_rect>(
+        &mut self,
+        draw: F,
+    ) -> mxcfb_rect {
+        let draw_area = draw(self.get_framebuffer_ref());
+        self.refresh_drawn_area(draw_area, UIConstraintRefresh::RefreshAndWait)
+    }
+
+    /// Refreshes `draw_area` as requested by `refresh` and returns it
+    pub(crate) fn refresh_drawn_area(
+        &mut self,
+        draw_area: mxcfb_rect,
+        refresh: UIConstraintRefresh,
+    ) -> mxcfb_rect {
+        let framebuffer = self.get_framebuffer_ref();
+        let (waveform, temperature, dither, wait) =
+            match refresh.parameters(waveform_mode::WAVEFORM_MODE_GC16_FAST) {
+                Some(parameters) => parameters,
+                None => return draw_area,
+            };
+
+        let result = framebuffer
+            .partial_refresh(
+                &draw_area,
+                PartialRefreshMode::Async,
+                waveform,
+                temperature,
+                dither,
+                0,
+            )
+            .and_then(|marker| match wait {
+                true => framebuffer.wait_refresh_complete(marker),
+                false => Ok(marker),
+            });
+        if let Err(e) = result {
+            warn!("Failed to refresh the drawn area: {0}", e);
+        }
         return draw_area;
     }
 
+    /// Shows `text` in a pill near the bottom center of the screen for `duration`, after
+    /// which the content underneath is restored. Returns immediately; toasts requested
+    /// while another one is on screen are queued and shown one after the other.
+    ///
+    /// Toasts are removed by `update_toasts`, which `dispatch_events` and `dispatch_input`
+    /// call as they go and apps running their own loop need to call. The content under a
+    /// toast is saved when it is shown and written back as is, so anything drawn under it
+    /// in the meantime is lost.
+    pub fn show_toast(&mut self, text: &str, duration: Duration) {
+        self.toasts.pending.push_back((text.to_owned(), duration));
+        self.update_toasts();
+    }
+
+    /// Removes the toast on screen once its duration is over and shows the next queued one,
+    /// if any. Returns how long the toast now on screen is left to stay, `None` if there is
+    /// none.
+    pub fn update_toasts(&mut self) -> Option<Duration> {
+        let expired = match self.toasts.shown {
+            Some(ref shown) => shown.until <= Instant::now(),
+            None => false,
+        };
+        if expired {
+            if let Some(shown) = self.toasts.shown.take() {
+                self.remove_toast(shown);
+            }
+        }
+        while self.toasts.shown.is_none() {
+            match self.toasts.pending.pop_front() {
+                Some((text, duration)) => self.toasts.shown = self.present_toast(&text, duration),
+                None => break,
+            }
+        }
+
+        let now = Instant::now();
+        self.toasts.shown.as_ref().map(|shown| match shown.until > now {
+            true => shown.until - now,
+            false => Duration::from_secs(0),
+        })
+    }
+
+    /// Draws a single toast and refreshes it. Returns what it covered, `None` if it couldn't
+    /// be shown.
+    fn present_toast(&mut self, text: &str, duration: Duration) -> Option<ShownToast> {
+        let framebuffer = self.get_framebuffer_ref();
+
+        let (text_rect, ascent) = framebuffer.measure_text_origin(text, TOAST_TEXT_SCALE);
+        let height = text_rect.height as usize + 2 * TOAST_PADDING_PX;
+        let width = std::cmp::min(
+            text_rect.width as usize + 2 * TOAST_PADDING_PX + height,
+            self.xres as usize,
+        );
+
+        let pill = mxcfb_rect {
+            top: self.yres.saturating_sub((TOAST_BOTTOM_MARGIN_PX + height) as u32),
+            left: (self.xres - width as u32) / 2,
+            height: height as u32,
+            width: width as u32,
+        };
+        let (top, left) = (pill.top as usize, pill.left as usize);
+
+        let background = match framebuffer.dump_region(pill) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Unable to save the background of a toast: {0}", e);
+                return None;
+            }
+        };
+
+        // Rounded off into a pill by half circles, clamped to half the height
+        framebuffer.fill_rounded_rect(
+            pill.top,
+            pill.left,
+            pill.height,
+            pill.width,
+            pill.height / 2,
+            color::BLACK,
+        );
+        framebuffer.draw_text(
+            top + TOAST_PADDING_PX + ascent,
+            left + width.saturating_sub(text_rect.width as usize) / 2,
+            text.to_owned(),
+            TOAST_TEXT_SCALE,
+            color::WHITE,
+        );
+        if let Err(e) = framebuffer.partial_refresh(
+            &pill,
+            PartialRefreshMode::Wait,
+            waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        ) {
+            warn!("Failed to refresh a toast: {0}", e);
+        }
+        Some(ShownToast {
+            pill,
+            background,
+            until: Instant::now() + duration,
+        })
+    }
+
+    /// Restores the content `shown` covered and refreshes it
+    fn remove_toast(&mut self, shown: ShownToast) {
+        let framebuffer = self.get_framebuffer_ref();
+        let pill = shown.pill;
+        if let Err(e) = framebuffer.restore_region(pill, &shown.background) {
+            warn!("Unable to restore the background of a toast: {0}", e);
+        }
+        if let Err(e) = framebuffer.partial_refresh(
+            &pill,
+            PartialRefreshMode::Wait,
+            waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        ) {
+            warn!("Failed to refresh the background of a toast: {0}", e);
+        }
+    }
+
+    /// Sets the `bitmap` drawn centered on the pen position while it is in range, `None`
+    /// removing the cursor. Dark pixels of the bitmap invert what is under them so that the
+    /// cursor stays visible on any content, light ones are transparent.
+    pub fn set_cursor(&mut self, bitmap: Option<image::DynamicImage>) {
+        self.hide_cursor();
+        self.cursor = bitmap.map(|b| PenCursor {
+            bitmap: b.to_luma(),
+            visible: true,
+            shown: None,
+        });
+    }
+
+    /// Shows or hides the cursor set with `set_cursor` without discarding it
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        if !visible {
+            self.hide_cursor();
+        }
+        if let Some(ref mut cursor) = self.cursor {
+            cursor.visible = visible;
+        }
+    }
+
+    /// Puts back the contents covered by the cursor, if it is on screen
+    fn hide_cursor(&mut self) {
+        let framebuffer = self.get_framebuffer_ref();
+        let shown = match self.cursor {
+            Some(ref mut cursor) => cursor.shown.take(),
+            None => None,
+        };
+        if let Some((rect, background)) = shown {
+            if let Err(e) = framebuffer.restore_region(rect, &background) {
+                warn!("Unable to restore the background of the cursor: {0}", e);
+            }
+            self.refresh_cursor_rect(&rect);
+        }
+    }
+
+    /// Erases the cursor from its last position and draws it centered on `(y, x)`
+    fn move_cursor(&mut self, y: usize, x: usize) {
+        match self.cursor {
+            Some(ref cursor) if cursor.visible => {}
+            _ => return,
+        };
+        self.hide_cursor();
+
+        let framebuffer = self.get_framebuffer_ref();
+        let (xres, yres) = (self.xres as usize, self.yres as usize);
+        let cursor = self.cursor.as_mut().unwrap();
+        let (width, height) = (cursor.bitmap.width() as usize, cursor.bitmap.height() as usize);
+        let top = y.saturating_sub(height / 2);
+        let left = x.saturating_sub(width / 2);
+        if top >= yres || left >= xres {
+            return;
+        }
+        let rect = mxcfb_rect {
+            top: top as u32,
+            left: left as u32,
+            height: std::cmp::min(height, yres - top) as u32,
+            width: std::cmp::min(width, xres - left) as u32,
+        };
+        let background = match framebuffer.dump_region(rect) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Unable to save the background of the cursor: {0}", e);
+                return;
+            }
+        };
+
+        // The bitmap is offset when the cursor is cut off by the top or left edge
+        let (skip_y, skip_x) = ((height / 2).saturating_sub(y), (width / 2).saturating_sub(x));
+        for row in 0..rect.height as usize {
+            for col in 0..rect.width as usize {
+                let bx = (col + skip_x) as u32;
+                let by = (row + skip_y) as u32;
+                if bx >= cursor.bitmap.width() || by >= cursor.bitmap.height() {
+                    continue;
+                }
+                if cursor.bitmap.get_pixel(bx, by).data[0] >= 0x80 {
+                    continue;
+                }
+                let under = framebuffer.read_pixel(top + row, left + col).as_native()[0];
+                framebuffer.write_pixel(top + row, left + col, color::GRAY(0xFF - under));
+            }
+        }
+        cursor.shown = Some((rect, background));
+        self.refresh_cursor_rect(&rect);
+    }
+
+    fn refresh_cursor_rect(&mut self, rect: &mxcfb_rect) {
+        if let Err(e) = self.get_framebuffer_ref().partial_refresh(
+            rect,
+            PartialRefreshMode::Async,
+            waveform_mode::WAVEFORM_MODE_GLR16,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        ) {
+            warn!("Failed to refresh the pen cursor: {0}", e);
+        }
+    }
+
+    /// Registers `element` under `name`, which is also stored in its `name` field. Returns
+    /// false without registering it if the name is already taken.
     pub fn add_element(&mut self, name: &str, element: Arc<RwLock<UIElementWrapper>>) -> bool {
         match self.ui_elements.contains_key(name) {
             true => false,
             false => {
+                element.write().unwrap().name = Some(name.to_owned());
                 self.ui_elements.insert(name.to_owned(), element);
                 true
             }
@@ -211,7 +1106,28 @@ impl<'a> ApplicationContext<'a> {
     }
 
     pub fn remove_element(&mut self, name: &str) -> bool {
-        return self.ui_elements.remove(name).is_some();
+        match self.ui_elements.remove(name) {
+            Some(element) => {
+                element.write().unwrap().name = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the element registered under `name` with `add_element`, wherever it is on
+    /// screen now
+    pub fn get_element_by_name(&self, name: &str) -> Option<Arc<RwLock<UIElementWrapper>>> {
+        self.ui_elements.get(name).map(Arc::clone)
+    }
+
+    /// Returns the rect the element `name` was last drawn to, `None` if there is no such
+    /// element or it hasn't been drawn yet
+    pub fn element_rect(&self, name: &str) -> Option<mxcfb_rect> {
+        match self.ui_elements.get(name) {
+            Some(element) => element.read().unwrap().last_drawn_rect,
+            None => None,
+        }
     }
 
     pub fn draw_element(&mut self, name: &str) -> bool {
@@ -219,14 +1135,16 @@ impl<'a> ApplicationContext<'a> {
         match self.ui_elements.get(name) {
             None => false,
             Some(element) => {
-                let h = {
+                let (h, clip, z) = {
                     let l = element.read().unwrap();
-                    l.onclick
+                    (l.onclick, l.clip, l.z)
                 };
                 let handler = match h {
                     Some(handler) => Some(ActiveRegionHandler {
                         handler,
                         element: Arc::clone(element),
+                        clip,
+                        z,
                     }),
                     _ => None,
                 };
@@ -241,22 +1159,225 @@ impl<'a> ApplicationContext<'a> {
             .iter()
             .map(|(_key, value)| Arc::clone(&value))
             .collect();
+        elems.sort_by_key(|e| e.read().unwrap().z);
 
         for element in &mut elems {
-            let h = {
+            let (h, clip, z) = {
                 let l = element.read().unwrap();
-                l.onclick
+                (l.onclick, l.clip, l.z)
             };
             let handler = match h {
                 Some(handler) => Some(ActiveRegionHandler {
                     handler,
                     element: element.clone(),
+                    clip,
+                    z,
                 }),
                 _ => None,
             };
             element.write().unwrap().draw(self, handler);
         }
     }
+
+    /// Draws all of `elements` in ascending `z` with their refreshes suppressed and then
+    /// refreshes the union of their old and new areas at once, which avoids the flicker of
+    /// refreshing each one on its own when redrawing a whole screen. The batch refresh is
+    /// waited on if any of the elements asks to wait for its refresh. Returns the refreshed
+    /// area, `None` if nothing was drawn or cleared.
+    pub fn draw_elements_batched(
+        &mut self,
+        elements: &[Arc<RwLock<UIElementWrapper>>],
+    ) -> Option<mxcfb_rect> {
+        let framebuffer = self.get_framebuffer_ref();
+        let mut damaged: Option<mxcfb_rect> = None;
+        let mut wait = false;
+        let mut elements = elements.to_vec();
+        elements.sort_by_key(|e| e.read().unwrap().z);
+        for element in &elements {
+            let (old_rect, refresh, h, clip, z) = {
+                let mut e = element.write().unwrap();
+                let refresh = std::mem::replace(&mut e.refresh, UIConstraintRefresh::NoRefresh);
+                (e.last_drawn_rect.take(), refresh, e.onclick, e.clip, e.z)
+            };
+            if let Some((_, _, _, true)) = refresh.parameters(waveform_mode::WAVEFORM_MODE_DU) {
+                wait = true;
+            }
+
+            // Cleared here rather than in `UIElementWrapper::draw`, which would refresh it
+            if let Some(rect) = old_rect {
+                framebuffer.fill_rect(
+                    rect.top as usize,
+                    rect.left as usize,
+                    rect.height as usize,
+                    rect.width as usize,
+                    color::WHITE,
+                );
+                self.remove_active_region_of(rect.top as u16, rect.left as u16, element);
+                damaged = Some(damaged.map_or(rect, |d| d.union(&rect)));
+            }
+
+            let handler = match h {
+                Some(handler) => Some(ActiveRegionHandler {
+                    handler,
+                    element: Arc::clone(element),
+                    clip,
+                    z,
+                }),
+                _ => None,
+            };
+            let mut e = element.write().unwrap();
+            e.draw(self, handler);
+            e.refresh = refresh;
+            if let Some(rect) = e.last_drawn_rect {
+                damaged = Some(damaged.map_or(rect, |d| d.union(&rect)));
+            }
+        }
+
+        if let Some(ref area) = damaged {
+            let mode = match wait {
+                true => PartialRefreshMode::Wait,
+                false => PartialRefreshMode::Async,
+            };
+            if let Err(e) = framebuffer.partial_refresh(
+                area,
+                mode,
+                waveform_mode::WAVEFORM_MODE_GC16_FAST,
+                display_temp::TEMP_USE_REMARKABLE_DRAW,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+            ) {
+                warn!("Failed to refresh a batch of elements: {0}", e);
+            }
+        }
+        damaged
+    }
+
+    /// Lays the children of the `UIElement::Container` named `name` out again, typically after
+    /// its position or width changed, and redraws them. Instead of each child refreshing its
+    /// old and new area on its own, the union of all of them is refreshed once. Returns that
+    /// union, `None` if there is no such container or it has no children.
+    pub fn reflow_container(&mut self, name: &str) -> Option<mxcfb_rect> {
+        let (top, left, children, width, spacing) = match self.ui_elements.get(name) {
+            Some(container) => {
+                let c = container.read().unwrap();
+                let (top, left) = c.resolve_position(self.yres as usize, self.xres as usize);
+                match c.inner {
+                    UIElement::Container {
+                        ref children,
+                        width,
+                        spacing,
+                    } => (top, left, children.clone(), width, spacing),
+                    _ => return None,
+                }
+            }
+            None => return None,
+        };
+
+        let framebuffer = self.get_framebuffer_ref();
+        let mut damaged: Option<mxcfb_rect> = None;
+        let (mut cursor_y, mut cursor_x, mut row_height) = (top, left, 0);
+        for child_name in &children {
+            let child = match self.ui_elements.get(child_name) {
+                Some(child) => Arc::clone(child),
+                None => continue,
+            };
+
+            let old_rect = {
+                let mut c = child.write().unwrap();
+                let predicted = c.predicted_rect(self);
+                let (height, width_px) = (predicted.height as usize, predicted.width as usize);
+                if cursor_x > left && cursor_x + width_px > left + width {
+                    cursor_y += row_height + spacing;
+                    cursor_x = left;
+                    row_height = 0;
+                }
+
+                // The position of a text is its baseline, so keep the offset between the
+                // position and the drawn rect when moving it
+                let (resolved_y, resolved_x) =
+                    c.resolve_position(self.yres as usize, self.xres as usize);
+                c.anchor = None;
+                c.y = cursor_y + resolved_y.saturating_sub(predicted.top as usize);
+                c.x = cursor_x + resolved_x.saturating_sub(predicted.left as usize);
+
+                cursor_x += width_px + spacing;
+                row_height = std::cmp::max(row_height, height);
+                c.last_drawn_rect.take()
+            };
+
+            // Cleared here rather than in `UIElementWrapper::draw`, which would refresh it
+            if let Some(rect) = old_rect {
+                framebuffer.fill_rect(
+                    rect.top as usize,
+                    rect.left as usize,
+                    rect.height as usize,
+                    rect.width as usize,
+                    color::WHITE,
+                );
+                self.remove_active_region_of(rect.top as u16, rect.left as u16, &child);
+                damaged = Some(damaged.map_or(rect, |d| d.union(&rect)));
+            }
+
+            let refresh = {
+                let mut c = child.write().unwrap();
+                std::mem::replace(&mut c.refresh, UIConstraintRefresh::NoRefresh)
+            };
+            self.draw_element(child_name);
+            let mut c = child.write().unwrap();
+            c.refresh = refresh;
+            if let Some(rect) = c.last_drawn_rect {
+                damaged = Some(damaged.map_or(rect, |d| d.union(&rect)));
+            }
+        }
+
+        if let Some(ref area) = damaged {
+            if let Err(e) = framebuffer.partial_refresh(
+                area,
+                PartialRefreshMode::Async,
+                waveform_mode::WAVEFORM_MODE_GC16_FAST,
+                display_temp::TEMP_USE_REMARKABLE_DRAW,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+            ) {
+                warn!("Failed to refresh a reflowed container: {0}", e);
+            }
+        }
+        damaged
+    }
+
+    /// Refreshes the union of the areas last drawn by the elements whose `group_id` is `id`
+    /// at once. The elements of a group would usually be drawn with
+    /// `UIConstraintRefresh::NoRefresh` and this called after drawing all of them. Returns
+    /// the refreshed area, `None` if no element of the group has been drawn.
+    pub fn refresh_group(&mut self, id: u32) -> Option<mxcfb_rect> {
+        let area = self.ui_elements
+            .values()
+            .filter_map(|element| {
+                let e = element.read().unwrap();
+                match e.group_id == Some(id) {
+                    true => e.last_drawn_rect,
+                    false => None,
+                }
+            })
+            .fold(None, |acc: Option<mxcfb_rect>, rect| {
+                Some(acc.map_or(rect, |a| a.union(&rect)))
+            });
+
+        if let Some(ref area) = area {
+            if let Err(e) = self.get_framebuffer_ref().partial_refresh(
+                area,
+                PartialRefreshMode::Async,
+                waveform_mode::WAVEFORM_MODE_GC16_FAST,
+                display_temp::TEMP_USE_REMARKABLE_DRAW,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+            ) {
+                warn!("Failed to refresh an element group: {0}", e);
+            }
+        }
+        area
+    }
+
     pub fn clear(&mut self, deep: bool) {
         let framebuffer = self.get_framebuffer_ref();
         let (yres, xres) = (
@@ -265,7 +1386,7 @@ impl<'a> ApplicationContext<'a> {
         );
         framebuffer.clear();
 
-        match deep {
+        let result = match deep {
             false => framebuffer.partial_refresh(
                 &mxcfb_rect {
                     top: 0,
@@ -287,6 +1408,85 @@ impl<'a> ApplicationContext<'a> {
                 true,
             ),
         };
+        if let Err(e) = result {
+            warn!("Failed to refresh the cleared screen: {0}", e);
+        }
+    }
+
+    /// Clears the residual image left on the panel by whatever was displayed before the
+    /// application started, leaving a white screen behind. Meant to be called once before
+    /// drawing the first frame, it blocks for a couple of seconds.
+    ///
+    /// The sequence is `WARM_UP_CYCLES` times a full black followed by a full white `GC16`
+    /// refresh, driving every pixel through both extremes so that they all start from the
+    /// same state, and then a final `INIT` refresh to clean up what the cycles left behind.
+    /// `set_min_full_refresh_interval` doesn't apply to these refreshes. Stops at the first
+    /// refresh that fails.
+    pub fn warm_up(&mut self) -> std::io::Result<()> {
+        let framebuffer = self.get_framebuffer_ref();
+        let (yres, xres) = (self.yres as usize, self.xres as usize);
+        let interval = std::mem::replace(
+            &mut framebuffer.min_full_refresh_interval,
+            Duration::from_secs(0),
+        );
+
+        let mut cycles = || -> std::io::Result<u32> {
+            for _ in 0..WARM_UP_CYCLES {
+                for &c in [color::BLACK, color::WHITE].iter() {
+                    framebuffer.fill_rect(0, 0, yres, xres, c);
+                    framebuffer.full_refresh(
+                        waveform_mode::WAVEFORM_MODE_GC16,
+                        display_temp::TEMP_USE_AMBIENT,
+                        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                        0,
+                        true,
+                    )?;
+                }
+            }
+            framebuffer.full_refresh(
+                waveform_mode::WAVEFORM_MODE_INIT,
+                display_temp::TEMP_USE_AMBIENT,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+                true,
+            )
+        };
+        let result = cycles();
+        framebuffer.min_full_refresh_interval = interval;
+        result.map(|_| ())
+    }
+
+    /// Sets how long after an accepted tap further taps on the same active region are
+    /// ignored, which filters out the repeated events of a single press. Defaults to 250ms.
+    pub fn set_debounce_duration(&mut self, duration: Duration) {
+        self.debounce_duration = duration;
+    }
+
+    /// Caps how often `present` commits a frame to `fps` times a second, 0 to not pace it
+    pub fn set_max_fps(&mut self, fps: u32) {
+        self.min_frame_interval = match fps {
+            0 => None,
+            fps => Some(Duration::from_nanos(1_000_000_000 / fps as u64)),
+        };
+    }
+
+    /// Sleeps for what is left of the frame interval set with `set_max_fps` since the
+    /// previous call, then commits everything drawn since the previous call as a single
+    /// refresh with `commit_frame` and begins the next frame. Returns the marker, 0 if nothing
+    /// changed. The first call only begins a frame unless `FramebufferRefresh::begin_frame`
+    /// was called already, and the frame stays open until `commit_frame` is called directly.
+    pub fn present(&mut self) -> std::io::Result<u32> {
+        if let (Some(interval), Some(last)) = (self.min_frame_interval, self.last_present) {
+            let elapsed = last.elapsed();
+            if elapsed < interval {
+                std::thread::sleep(interval - elapsed);
+            }
+        }
+        self.last_present = Some(Instant::now());
+        let framebuffer = self.get_framebuffer_ref();
+        let marker = framebuffer.commit_frame();
+        framebuffer.begin_frame();
+        marker
     }
 
     pub fn stop(&mut self) {
@@ -294,8 +1494,6 @@ impl<'a> ApplicationContext<'a> {
     }
 
     pub fn dispatch_events(&mut self, ringbuffer_size: usize, event_read_chunksize: usize) {
-        let appref = self.upgrade_ref();
-
         let ringbuffer = rb::SpscRb::new(ringbuffer_size);
         let producer = ringbuffer.producer();
         let unified = unsafe {
@@ -321,77 +1519,272 @@ impl<'a> ApplicationContext<'a> {
 
         let mut last_active_region_gesture_id: i32 = -1;
         while self.running.load(Ordering::Relaxed) {
+            // Polled while a toast is on screen so that it is removed on time without input
+            if self.update_toasts().is_some() {
+                match consumer.read(&mut buf) {
+                    Ok(read) => for &ev in buf[..read].iter() {
+                        self.handle_input_event(ev, &mut last_active_region_gesture_id);
+                    },
+                    Err(_) => {
+                        std::thread::sleep(Duration::from_millis(TOAST_POLL_INTERVAL_MS))
+                    }
+                }
+                continue;
+            }
             let _read = consumer.read_blocking(&mut buf).unwrap();
             for &ev in buf.iter() {
-                match ev {
-                    InputEvent::GPIO { event } => {
-                        (self.on_button)(appref, event);
-                    }
-                    InputEvent::MultitouchEvent { event } => {
-                        // Check for and notify clickable active regions for multitouch events
-                        match event {
-                            MultitouchEvent::Touch {
-                                gesture_seq,
-                                finger_id: _,
-                                y,
-                                x,
-                            } => {
-                                let gseq = gesture_seq as i32;
-                                if last_active_region_gesture_id != gseq {
-                                    match self.find_active_region(y, x) {
-                                        Some((h, _)) => {
-                                            (h.handler)(appref, Arc::clone(&h.element));
+                self.handle_input_event(ev, &mut last_active_region_gesture_id);
+            }
+        }
+
+        // Wait for all threads to join
+        gpio_thread.join().unwrap();
+        wacom_thread.join().unwrap();
+        touch_thread.join().unwrap();
+    }
+
+    /// Same as `dispatch_events` but consumes the events of `events`, as sent by an
+    /// `input::ev::EvDevContext` or any other producer, until `stop` is called or all of the
+    /// senders are gone
+    pub fn dispatch_input(&mut self, events: &mpsc::Receiver<InputEvent>) {
+        self.running.store(true, Ordering::Relaxed);
+
+        let mut last_active_region_gesture_id: i32 = -1;
+        while self.running.load(Ordering::Relaxed) {
+            let received = match self.update_toasts() {
+                // Woken up when the toast on screen is due to be removed
+                Some(remaining) => match events.recv_timeout(remaining) {
+                    Ok(ev) => Some(ev),
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => None,
+                },
+                None => events.recv().ok(),
+            };
+            match received {
+                Some(ev) => self.handle_input_event(ev, &mut last_active_region_gesture_id),
+                None => break,
+            }
+        }
+    }
+
+    /// Invokes the callbacks and active regions concerned by `ev`. A touch only triggers an
+    /// active region if it starts a gesture other than `last_gesture_id`.
+    fn handle_input_event(&mut self, ev: InputEvent, last_gesture_id: &mut i32) {
+        let appref = self.upgrade_ref();
+        match ev {
+            InputEvent::GPIO { event } => {
+                (self.on_button)(appref, event);
+            }
+            InputEvent::MultitouchEvent { event } => {
+                // Check for and notify clickable active regions for multitouch events
+                match event {
+                    MultitouchEvent::Touch {
+                        gesture_seq,
+                        finger_id: _,
+                        y,
+                        x,
+                    } => {
+                        let gseq = gesture_seq as i32;
+                        if *last_gesture_id != gseq {
+                            match self.find_active_region(y, x) {
+                                Some((h, _)) => {
+                                    // Kept on the element, which unlike its region survives
+                                    // being redrawn
+                                    let now = Instant::now();
+                                    let bounced = {
+                                        let mut element = h.element.write().unwrap();
+                                        let bounced = match element.last_triggered {
+                                            Some(last) => {
+                                                now.duration_since(last) < self.debounce_duration
+                                            }
+                                            None => false,
+                                        };
+                                        if !bounced {
+                                            element.last_triggered = Some(now);
                                         }
-                                        _ => {}
+                                        bounced
                                     };
-                                    last_active_region_gesture_id = gseq;
+                                    if !bounced {
+                                        h.element.read().unwrap().press_feedback(appref);
+                                        (h.handler)(appref, Arc::clone(&h.element));
+                                    }
                                 }
-                            }
-                            _ => {}
-                        };
-                        (self.on_touch)(appref, event);
+                                _ => {}
+                            };
+                            *last_gesture_id = gseq;
+                        }
                     }
-                    InputEvent::WacomEvent { event } => {
-                        (self.on_wacom)(appref, event);
+                    _ => {}
+                };
+                (self.on_touch)(appref, event);
+            }
+            InputEvent::WacomEvent { event } => {
+                // The cursor is taken off before the callback runs so that its saved background
+                // doesn't undo whatever the app draws underneath it
+                match event {
+                    WacomEvent::Hover { .. }
+                    | WacomEvent::Draw { .. }
+                    | WacomEvent::InstrumentChange { state: false, .. } => {
+                        self.hide_cursor();
                     }
                     _ => {}
-                }
+                };
+                (self.on_wacom)(appref, event);
+                match event {
+                    WacomEvent::Hover { y, x, .. } | WacomEvent::Draw { y, x, .. } => {
+                        self.move_cursor(y as usize, x as usize);
+                    }
+                    _ => {}
+                };
             }
+            _ => {}
         }
-
-        // Wait for all threads to join
-        gpio_thread.join().unwrap();
-        wacom_thread.join().unwrap();
-        touch_thread.join().unwrap();
     }
 
-    pub fn find_active_region(&self, y: u16, x: u16) -> Option<(&ActiveRegionHandler, ItemId)> {
-        let matches = self.active_regions.query(geom::Rect::centered_with_radius(
-            &geom::Point {
-                y: y as f32,
-                x: x as f32,
-            },
-            2.0,
-        ));
-        match matches.len() {
-            0 => None,
-            _ => {
-                let res = matches.first().unwrap();
-                Some((res.0, res.2.clone()))
+    /// Returns the input events read since the previous call without blocking, for apps that
+    /// run their own main loop instead of `dispatch_events`. The first call starts reading
+    /// `DEFAULT_INPUT_DEVICES` with `EvDevContext`, so it likely returns nothing. Neither the
+    /// callbacks nor the active regions are invoked for polled events. Not to be mixed with
+    /// `dispatch_events`.
+    pub fn poll_input(&mut self) -> Vec<InputEvent> {
+        if self.polled_input.is_none() {
+            self.polled_input = Some(ev::EvDevContext::start(&ev::DEFAULT_INPUT_DEVICES));
+        }
+
+        let mut events = Vec::new();
+        if let Some((_, ref receiver)) = self.polled_input {
+            while let Ok(event) = receiver.try_recv() {
+                events.push(event);
             }
         }
+        events
+    }
+
+    /// Returns every active region whose box contains `(y, x)`, regardless of clipping, the
+    /// highest z first and then the most recently created first
+    fn query_active_regions(&self, y: u16, x: u16) -> Vec<(&ActiveRegionHandler, ItemId)> {
+        let mut found: Vec<(&ActiveRegionHandler, ItemId)> = self.active_regions
+            .query(geom::Rect::centered_with_radius(
+                &geom::Point {
+                    y: y as f32,
+                    x: x as f32,
+                },
+                2.0,
+            ))
+            .into_iter()
+            .map(|(handler, _, id)| (handler, id))
+            .collect();
+        // Ids are handed out in increasing order, so the last inserted region sorts first
+        // among those of the same z
+        found.sort_by(|a, b| (b.0.z, b.1).cmp(&(a.0.z, a.1)));
+        found
+    }
+
+    /// Returns the active region that a tap at `(y, x)` lands on. Regions whose clip bounds
+    /// don't contain the point are skipped, so the parts of an element that are scrolled or
+    /// clipped away don't catch taps. Overlapping regions are searched from the highest
+    /// z down, and among those of the same z from the most recently created one down.
+    pub fn find_active_region(&self, y: u16, x: u16) -> Option<(&ActiveRegionHandler, ItemId)> {
+        self.query_active_regions(y, x)
+            .into_iter()
+            .find(|&(handler, _)| match handler.clip {
+                Some(mut clip) => clip.contains_point(y as u32, x as u32),
+                None => true,
+            })
+    }
+
+    /// Returns a copy of the handler a tap at `(y, x)` would be dispatched to, e.g. to check
+    /// where a layout placed a button. When regions overlap, the one with the highest z
+    /// whose clip bounds contain the point wins, as with `find_active_region`.
+    pub fn active_region_at(&self, y: u16, x: u16) -> Option<ActiveRegionHandler> {
+        self.find_active_region(y, x).map(|(handler, _)| handler.clone())
     }
 
     pub fn remove_active_region_at_point(&mut self, y: u16, x: u16) -> bool {
-        match self.find_active_region(y, x) {
-            Some((_, itemid)) => match self.active_regions.remove(itemid) {
-                Some(_) => true,
-                _ => false,
-            },
+        let found = self.query_active_regions(y, x).first().map(|&(_, id)| id);
+        match found {
+            Some(itemid) => self.active_regions.remove(itemid).is_some(),
             _ => false,
         }
     }
 
+    /// Removes the active region of `element` that contains `(y, x)`, leaving those of other
+    /// elements stacked on the same point in place. Returns whether one was removed.
+    pub fn remove_active_region_of(
+        &mut self,
+        y: u16,
+        x: u16,
+        element: &Arc<RwLock<UIElementWrapper>>,
+    ) -> bool {
+        let found = self.find_active_region_of(y, x, element);
+        match found {
+            Some(itemid) => self.active_regions.remove(itemid).is_some(),
+            None => false,
+        }
+    }
+
+    /// Returns the id of the active region of `element` that contains `(y, x)`, if any
+    fn find_active_region_of(
+        &self,
+        y: u16,
+        x: u16,
+        element: &Arc<RwLock<UIElementWrapper>>,
+    ) -> Option<ItemId> {
+        self.query_active_regions(y, x)
+            .into_iter()
+            .find(|&(handler, _)| Arc::ptr_eq(&handler.element, element))
+            .map(|(_, id)| id)
+    }
+
+    /// Whether `element` has an active region containing `(y, x)`
+    pub fn has_active_region_of(
+        &self,
+        y: u16,
+        x: u16,
+        element: &Arc<RwLock<UIElementWrapper>>,
+    ) -> bool {
+        self.find_active_region_of(y, x, element).is_some()
+    }
+
+    /// Returns the rects of all registered active regions, in no particular order
+    pub fn active_regions(&self) -> Vec<mxcfb_rect> {
+        self.active_regions
+            .iter()
+            .map(|(_id, &(_, ref rect))| mxcfb_rect {
+                top: rect.top() as u32,
+                left: rect.left() as u32,
+                height: rect.height() as u32,
+                width: rect.width() as u32,
+            })
+            .collect()
+    }
+
+    /// Outlines every registered active region on the screen and refreshes the outlines so
+    /// that the actual hit areas can be compared to what has been drawn
+    pub fn debug_draw_active_regions(&mut self) {
+        let framebuffer = self.get_framebuffer_ref();
+        for rect in self.active_regions() {
+            framebuffer.draw_rect(
+                rect.top as usize,
+                rect.left as usize,
+                rect.height as usize,
+                rect.width as usize,
+                2,
+                color::BLACK,
+            );
+            if let Err(e) = framebuffer.partial_refresh(
+                &rect,
+                PartialRefreshMode::Async,
+                waveform_mode::WAVEFORM_MODE_DU,
+                display_temp::TEMP_USE_REMARKABLE_DRAW,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+            ) {
+                warn!("Failed to refresh the outline of an active region: {0}", e);
+            }
+        }
+    }
+
     pub fn create_active_region(
         &mut self,
         y: u16,
@@ -400,9 +1793,44 @@ impl<'a> ApplicationContext<'a> {
         width: u16,
         handler: ActiveRegionFunction,
         element: Arc<RwLock<UIElementWrapper>>,
+    ) {
+        self.create_clipped_active_region(y, x, height, width, handler, element, None);
+    }
+
+    /// Same as `create_active_region`, only responding to taps that also fall within `clip`
+    pub fn create_clipped_active_region(
+        &mut self,
+        y: u16,
+        x: u16,
+        height: u16,
+        width: u16,
+        handler: ActiveRegionFunction,
+        element: Arc<RwLock<UIElementWrapper>>,
+        clip: Option<mxcfb_rect>,
+    ) {
+        self.create_layered_active_region(y, x, height, width, handler, element, clip, 0);
+    }
+
+    /// Same as `create_clipped_active_region`, catching taps before the overlapping regions
+    /// with a lower `z`
+    pub fn create_layered_active_region(
+        &mut self,
+        y: u16,
+        x: u16,
+        height: u16,
+        width: u16,
+        handler: ActiveRegionFunction,
+        element: Arc<RwLock<UIElementWrapper>>,
+        clip: Option<mxcfb_rect>,
+        z: i32,
     ) {
         self.active_regions.insert_with_box(
-            ActiveRegionHandler { handler, element },
+            ActiveRegionHandler {
+                handler,
+                element,
+                clip,
+                z,
+            },
             geom::Rect::from_points(
                 &geom::Point {
                     x: x as f32,
@@ -0,0 +1,76 @@
+use std;
+use std::fmt;
+use std::io;
+
+use framebuffer::core::AbiMismatch;
+
+/// Error type returned by the fallible functions of the crate
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a device or sysfs attribute failed
+    Io(io::Error),
+    /// The ioctl `name` failed with `errno`
+    Ioctl { name: &'static str, errno: i32 },
+    /// The kernel seems to expect different structures, see `FramebufferBase::verify_abi`
+    /// and `FramebufferBase::probe_update_ioctls`
+    AbiMismatch(AbiMismatch),
+    /// A region doesn't fit within the screen
+    OutOfBounds,
+    /// Data such as an image or a font is in a format that can't be handled
+    UnsupportedFormat(String),
+    /// The hardware needed for the operation isn't present on this device
+    Unsupported(&'static str),
+    /// An argument doesn't make sense, e.g. a region with no area
+    InvalidArgument(&'static str),
+    /// Data read back from the system couldn't be interpreted
+    InvalidData(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "IO error: {0}", e),
+            Error::Ioctl { name, errno } => write!(f, "{0} failed with errno {1}", name, errno),
+            Error::AbiMismatch(ref e) => write!(f, "ABI mismatch: {0}", e),
+            Error::OutOfBounds => write!(f, "Region out of bounds"),
+            Error::UnsupportedFormat(ref what) => write!(f, "Unsupported format: {0}", what),
+            Error::Unsupported(what) => write!(f, "Unsupported on this device: {0}", what),
+            Error::InvalidArgument(what) => write!(f, "Invalid argument: {0}", what),
+            Error::InvalidData(ref what) => write!(f, "Invalid data: {0}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "IO error",
+            Error::Ioctl { .. } => "ioctl failed",
+            Error::AbiMismatch(_) => "ABI mismatch",
+            Error::OutOfBounds => "region out of bounds",
+            Error::UnsupportedFormat(_) => "unsupported format",
+            Error::Unsupported(_) => "unsupported on this device",
+            Error::InvalidArgument(_) => "invalid argument",
+            Error::InvalidData(_) => "invalid data",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<AbiMismatch> for Error {
+    fn from(e: AbiMismatch) -> Error {
+        Error::AbiMismatch(e)
+    }
+}
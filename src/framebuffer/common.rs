@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 #![allow(non_camel_case_types)]
+use cgmath;
 use std;
 use framebuffer::mxcfb::*;
 
@@ -53,6 +54,32 @@ pub enum color {
 }
 
 impl color {
+    /// The 16 gray levels the panel can tell apart, from black to white
+    pub const GRAY_LEVELS: [color; 16] = [
+        color::GRAY(0x00),
+        color::GRAY(0x11),
+        color::GRAY(0x22),
+        color::GRAY(0x33),
+        color::GRAY(0x44),
+        color::GRAY(0x55),
+        color::GRAY(0x66),
+        color::GRAY(0x77),
+        color::GRAY(0x88),
+        color::GRAY(0x99),
+        color::GRAY(0xAA),
+        color::GRAY(0xBB),
+        color::GRAY(0xCC),
+        color::GRAY(0xDD),
+        color::GRAY(0xEE),
+        color::GRAY(0xFF),
+    ];
+
+    /// Gray of luminance `level`, 0 being black and 255 white. The framebuffer stores the
+    /// level in every component of a pixel, so this is `color::GRAY(level)`.
+    pub fn gray(level: u8) -> color {
+        color::GRAY(level)
+    }
+
     pub fn as_native(&self) -> [u8; 4] {
         // No need to over-optimize here and return a reference because 4 x u8 (1byte) = 4bytes
         match self {
@@ -113,6 +140,63 @@ pub const DRAWING_QUANT_BIT: i32 = 0x76143b24;
 pub const DRAWING_QUANT_BIT_2: i32 = 0x75e7bb24;
 pub const DRAWING_QUANT_BIT_3: i32 = 0x53ed4;
 
+/// Presets for the `quant_bit` parameter of the refresh functions.
+///
+/// The EPDC only looks at `quant_bit` when `dither_mode` selects one of the PxP dithering
+/// algorithms (`0 < dither_mode < EPDC_FLAG_USE_DITHERING_MAX`). It is then the number of bits
+/// per pixel the content is quantized to before being dithered. For any other `dither_mode`
+/// (including `EPDC_FLAG_USE_DITHERING_PASSTHROUGH`) the driver ignores it, which is also why
+/// the seemingly random `DRAWING_QUANT_BIT*` values used by `xochitl` have no ill effect.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum QuantBits {
+    /// No quantization, use when not dithering
+    None = 0,
+    /// 1 bit -- black and white only. Pairs with `WAVEFORM_MODE_DU` for text and line art.
+    Monochrome = 1,
+    /// 2 bits -- 4 gray levels. Pairs with `WAVEFORM_MODE_DU4` and `WAVEFORM_MODE_GL4`.
+    Gray4 = 2,
+    /// 4 bits -- 16 gray levels, the full range of the panel. Pairs with `WAVEFORM_MODE_GC16`
+    /// for photos.
+    Gray16 = 4,
+}
+
+impl QuantBits {
+    /// Preset for text and other bilevel content
+    pub fn text() -> QuantBits {
+        QuantBits::Monochrome
+    }
+
+    /// Preset for photos and other content with many gray levels
+    pub fn photo() -> QuantBits {
+        QuantBits::Gray16
+    }
+}
+
+/// Smallest `quant_bit` the PxP dithering accepts
+pub const MIN_QUANT_BIT: i32 = 1;
+/// Largest `quant_bit` the PxP dithering accepts
+pub const MAX_QUANT_BIT: i32 = 7;
+
+/// Exclusive upper bound of the `dither_mode` values that make the driver use `quant_bit`
+const EPDC_FLAG_USE_DITHERING_MAX: i32 = 5;
+
+/// Returns the `quant_bit` that will actually be sent for `dither_mode`. When the driver
+/// makes use of it, out of range values are clamped to `[MIN_QUANT_BIT, MAX_QUANT_BIT]`
+/// with a warning. Otherwise it is ignored by the driver and returned as is.
+pub fn validate_quant_bit(dither_mode: i32, quant_bit: i32) -> i32 {
+    if dither_mode <= 0 || dither_mode >= EPDC_FLAG_USE_DITHERING_MAX {
+        return quant_bit;
+    }
+    let clamped = ::std::cmp::min(::std::cmp::max(quant_bit, MIN_QUANT_BIT), MAX_QUANT_BIT);
+    if clamped != quant_bit {
+        warn!(
+            "quant_bit {0} is out of range for dither_mode {1}, using {2} instead",
+            quant_bit, dither_mode, clamped
+        );
+    }
+    clamped
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C)]
 pub struct mxcfb_rect {
@@ -139,10 +223,155 @@ impl mxcfb_rect {
     }
 }
 
+/// Saturates `v` into the range of an `i32`
+fn to_signed(v: u32) -> i32 {
+    ::std::cmp::min(v, ::std::i32::MAX as u32) as i32
+}
+
+impl mxcfb_rect {
+    /// Returns the top left corner as a point with `x` being the column and `y` the row
+    pub fn top_left(&self) -> cgmath::Point2<i32> {
+        cgmath::Point2 {
+            x: to_signed(self.left),
+            y: to_signed(self.top),
+        }
+    }
+
+    /// Returns the extent of the rect with `x` being the width and `y` the height
+    pub fn size(&self) -> cgmath::Vector2<i32> {
+        cgmath::Vector2 {
+            x: to_signed(self.width),
+            y: to_signed(self.height),
+        }
+    }
+
+    /// Creates the rect spanning from `top_left` to `bottom_right`, exclusive. The parts at
+    /// negative coordinates are clipped away as they can't be on screen. The result is empty
+    /// if the corners are swapped or the rect lies entirely off screen.
+    pub fn from_corners(
+        top_left: cgmath::Point2<i32>,
+        bottom_right: cgmath::Point2<i32>,
+    ) -> mxcfb_rect {
+        let (left, top) = (::std::cmp::max(top_left.x, 0), ::std::cmp::max(top_left.y, 0));
+        let (right, bottom) = (
+            ::std::cmp::max(bottom_right.x, left),
+            ::std::cmp::max(bottom_right.y, top),
+        );
+        mxcfb_rect {
+            top: top as u32,
+            left: left as u32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        }
+    }
+}
+
+/// Converts a rect into its top left corner and size, see `mxcfb_rect::top_left` and
+/// `mxcfb_rect::size`
+impl From<mxcfb_rect> for (cgmath::Point2<i32>, cgmath::Vector2<i32>) {
+    fn from(rect: mxcfb_rect) -> Self {
+        (rect.top_left(), rect.size())
+    }
+}
+
+/// Converts a top left corner and size into a rect, clipping away negative coordinates and
+/// treating a negative size as empty
+impl From<(cgmath::Point2<i32>, cgmath::Vector2<i32>)> for mxcfb_rect {
+    fn from((origin, size): (cgmath::Point2<i32>, cgmath::Vector2<i32>)) -> Self {
+        let size = cgmath::Vector2 {
+            x: ::std::cmp::max(size.x, 0),
+            y: ::std::cmp::max(size.y, 0),
+        };
+        mxcfb_rect::from_corners(
+            origin,
+            cgmath::Point2 {
+                x: origin.x.saturating_add(size.x),
+                y: origin.y.saturating_add(size.y),
+            },
+        )
+    }
+}
+
+impl mxcfb_rect {
+    /// Returns the area covered by both `self` and `other`, if any
+    pub fn intersection(&self, other: &mxcfb_rect) -> Option<mxcfb_rect> {
+        let top = ::std::cmp::max(self.top, other.top);
+        let left = ::std::cmp::max(self.left, other.left);
+        let bottom = ::std::cmp::min(self.top + self.height, other.top + other.height);
+        let right = ::std::cmp::min(self.left + self.width, other.left + other.width);
+        match bottom > top && right > left {
+            true => Some(mxcfb_rect {
+                top,
+                left,
+                height: bottom - top,
+                width: right - left,
+            }),
+            false => None,
+        }
+    }
+
+    /// Returns the smallest rect covering both `self` and `other`
+    pub fn union(&self, other: &mxcfb_rect) -> mxcfb_rect {
+        let top = ::std::cmp::min(self.top, other.top);
+        let left = ::std::cmp::min(self.left, other.left);
+        let bottom = ::std::cmp::max(self.top + self.height, other.top + other.height);
+        let right = ::std::cmp::max(self.left + self.width, other.left + other.width);
+        mxcfb_rect {
+            top,
+            left,
+            height: bottom - top,
+            width: right - left,
+        }
+    }
+
+    /// Returns the area of `self` that isn't covered by `other` as up to four disjoint rects:
+    /// full width bands above and below `other`, then the parts to its left and right
+    pub fn difference(&self, other: &mxcfb_rect) -> Vec<mxcfb_rect> {
+        let overlap = match self.intersection(other) {
+            Some(r) => r,
+            None => return vec![*self],
+        };
+        let (bottom, right) = (self.top + self.height, self.left + self.width);
+        let (overlap_bottom, overlap_right) =
+            (overlap.top + overlap.height, overlap.left + overlap.width);
+        let parts = [
+            mxcfb_rect {
+                top: self.top,
+                left: self.left,
+                height: overlap.top - self.top,
+                width: self.width,
+            },
+            mxcfb_rect {
+                top: overlap_bottom,
+                left: self.left,
+                height: bottom - overlap_bottom,
+                width: self.width,
+            },
+            mxcfb_rect {
+                top: overlap.top,
+                left: self.left,
+                height: overlap.height,
+                width: overlap.left - self.left,
+            },
+            mxcfb_rect {
+                top: overlap.top,
+                left: overlap_right,
+                height: overlap.height,
+                width: right - overlap_right,
+            },
+        ];
+        parts
+            .iter()
+            .filter(|r| r.width != 0 && r.height != 0)
+            .cloned()
+            .collect()
+    }
+}
+
 impl mxcfb_rect {
     pub fn contains_point(&mut self, y: u32, x: u32) -> bool {
         x >= self.left && x < (self.left + self.width) && y >= self.top
-            && x < (self.top + self.height)
+            && y < (self.top + self.height)
     }
 }
 
@@ -195,7 +424,7 @@ pub enum update_mode {
     UPDATE_MODE_FULL = 1,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum dither_mode {
     EPDC_FLAG_USE_DITHERING_PASSTHROUGH = 0x0,
     EPDC_FLAG_USE_DITHERING_DRAWING = 0x1,
@@ -215,7 +444,7 @@ pub enum dither_mode {
     EPDC_FLAG_EXP8 = 0x7ed3d2c0,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum waveform_mode {
     /// (Recommended) Screen goes to white
     /// (flashes black/white once to clear ghosting when used with UPDATE_MODE_FULL)
@@ -265,7 +494,7 @@ pub enum waveform_mode {
     WAVEFORM_MODE_AUTO = 257,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum display_temp {
     /// Seems to have the best draw latency. Perhaps the rule of thumb here is the lower the faster.
     /// `xochitl` seems to use this value.
@@ -276,4 +505,17 @@ pub enum display_temp {
     TEMP_USE_PAPYRUS = 0x1001,
     /// High draw latency again
     TEMP_USE_MAX = 0xFFFF,
+    /// Never sent to the EPDC, the refresh functions substitute the default temperature of
+    /// the framebuffer for it, see `Framebuffer::set_default_temp`
+    TEMP_AUTO = -1,
+}
+
+impl display_temp {
+    /// Returns `default` in place of `TEMP_AUTO`, otherwise `self`
+    pub fn resolve(self, default: display_temp) -> display_temp {
+        match self {
+            display_temp::TEMP_AUTO => default,
+            other => other,
+        }
+    }
 }
@@ -7,30 +7,273 @@ use mmap::MemoryMap;
 
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, Mutex};
 use std::fs::{File, OpenOptions};
+use std::time::{Duration, Instant};
 
+use error::Error;
 use framebuffer;
+use framebuffer::common::{display_temp, mxcfb_rect};
+use framebuffer::draw::FontId;
+#[cfg(feature = "draw-stats")]
+use framebuffer::draw::DrawStats;
+use framebuffer::mxcfb::{mxcfb_update_data, mxcfb_update_marker_data};
+use framebuffer::refresh::{RefreshSample, UpdateLog};
 use framebuffer::screeninfo::{FixScreeninfo, VarScreeninfo};
 use framebuffer::common::{FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO,
                           MXCFB_DISABLE_EPDC_ACCESS, MXCFB_ENABLE_EPDC_ACCESS,
-                          MXCFB_SET_AUTO_UPDATE_MODE, MXCFB_SET_UPDATE_SCHEME};
+                          MXCFB_SET_AUTO_UPDATE_MODE, MXCFB_SET_UPDATE_SCHEME,
+                          MXCFB_WAIT_FOR_UPDATE_COMPLETE};
 
 use rusttype::{Font, FontCollection};
 
+/// Size of `mxcfb_update_data` in the mxcfb header of the reMarkable kernel
+const EXPECTED_UPDATE_DATA_SIZE: usize = 72;
+/// Size of `mxcfb_update_marker_data` in the mxcfb header of the reMarkable kernel
+const EXPECTED_UPDATE_MARKER_DATA_SIZE: usize = 8;
+
+/// Marker that is never handed out, used to probe `MXCFB_WAIT_FOR_UPDATE_COMPLETE`
+const ABI_PROBE_MARKER: u32 = 0xFFFF_FFFF;
+
+/// Reason why `verify_abi` or `probe_update_ioctls` believes the kernel expects different
+/// structures than ours
+#[derive(Clone, Debug, PartialEq)]
+pub enum AbiMismatch {
+    /// One of the structs passed to the EPDC ioctls doesn't have the expected size
+    StructSize {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// The kernel doesn't know the ioctl number, which encodes the size of its argument
+    UnknownIoctl { name: &'static str },
+    /// The reported screen info doesn't describe a usable framebuffer
+    ScreenInfo(String),
+}
+
+impl ::std::fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            AbiMismatch::StructSize {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{0} is {1} bytes but {2} were expected", name, actual, expected),
+            AbiMismatch::UnknownIoctl { name } => write!(f, "{0} is unknown to the kernel", name),
+            AbiMismatch::ScreenInfo(ref reason) => write!(f, "Unexpected screen info: {0}", reason),
+        }
+    }
+}
+
 /// Framebuffer struct containing the state (latest update marker etc.)
 /// along with the var/fix screeninfo structs.
 pub struct Framebuffer<'a> {
     pub device: File,
     pub frame: MemoryMap,
-    pub marker: ::std::sync::atomic::AtomicU32,
+    /// Marker assigned to the next refresh, shared with the `RefreshHandle`s
+    pub marker: Arc<AtomicU32>,
+    /// Held while a marker is assigned and its update submitted
+    pub refresh_lock: Arc<Mutex<()>>,
     pub default_font: Font<'a>,
+    /// Fonts added with `load_font`, indexed by their `FontId`
+    pub fonts: Vec<Font<'a>>,
     pub var_screen_info: VarScreeninfo,
     pub fix_screen_info: FixScreeninfo,
+    /// Copy of the frame that erasing strokes restore from, see `capture_background`
+    pub background: Option<Vec<u8>>,
+    /// Refreshes recorded while profiling is enabled, see `set_refresh_profiling`
+    pub refresh_timeline: Option<Vec<RefreshSample>>,
+    /// `full_refresh` calls within this long of the previous one are ignored
+    pub min_full_refresh_interval: Duration,
+    /// When the last full refresh completed, or was submitted if it wasn't waited on
+    pub last_full_refresh: Option<Instant>,
+    /// Region every draw and refresh is clipped to, see `set_viewport`
+    pub viewport: Option<mxcfb_rect>,
+    /// Number of partial refreshes after which the next one becomes a full refresh, 0 to
+    /// never promote them. See `set_auto_full_refresh`.
+    pub auto_full_refresh_every: u32,
+    /// Partial refreshes submitted since the last full refresh
+    pub partial_refreshes_since_full: u32,
+    /// Copy of the frame that draws go to between `begin_frame` and `commit_frame`
+    pub back_buffer: Option<Vec<u8>>,
+    /// Where refreshes are recorded instead of being submitted, see `in_memory`
+    pub update_log: Option<UpdateLog>,
+    /// Marker and region of the updates submitted and not waited on yet, oldest first
+    pub in_flight_updates: Vec<(u32, mxcfb_rect)>,
+    /// Temperature refreshes given `TEMP_AUTO` are submitted with, see `set_default_temp`
+    pub default_temp: display_temp,
+    /// Counters accumulated by the drawing functions, see `take_stats`
+    #[cfg(feature = "draw-stats")]
+    pub draw_stats: DrawStats,
 }
 
 unsafe impl<'a> Send for Framebuffer<'a> {}
 unsafe impl<'a> Sync for Framebuffer<'a> {}
 
+/// Font the text functions of `FramebufferDraw` use unless given another one
+fn builtin_font() -> Font<'static> {
+    let font_data = include_bytes!("../../assets/DejaVuSans.ttf");
+    FontCollection::from_bytes(font_data as &[u8])
+        .into_font()
+        .unwrap()
+}
+
+impl<'a> Framebuffer<'a> {
+    /// Creates a 32 bits per pixel framebuffer of `yres` by `xres` held in memory rather than
+    /// mapped from `/dev/fb0`, so that code drawing and refreshing can be tested off-device.
+    /// Draws and reads work as usual. Refreshes only go to a log that `submitted_updates`
+    /// returns, with their markers assigned as usual, and complete right away.
+    pub fn in_memory(yres: u32, xres: u32) -> Framebuffer<'a> {
+        let var_screen_info = VarScreeninfo {
+            xres,
+            yres,
+            xres_virtual: xres,
+            yres_virtual: yres,
+            bits_per_pixel: 32,
+            ..Default::default()
+        };
+        let fix_screen_info = FixScreeninfo {
+            line_length: xres * 4,
+            smem_len: xres * 4 * yres,
+            ..Default::default()
+        };
+
+        let frame_length = fix_screen_info.smem_len as usize;
+        let mem_map = MemoryMap::new(
+            frame_length,
+            &[mmap::MapOption::MapReadable, mmap::MapOption::MapWritable],
+        ).unwrap();
+
+        Framebuffer {
+            marker: Arc::new(AtomicU32::new(1)),
+            refresh_lock: Arc::new(Mutex::new(())),
+            device: OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/null")
+                .unwrap(),
+            frame: mem_map,
+            default_font: builtin_font(),
+            fonts: Vec::new(),
+            var_screen_info,
+            fix_screen_info,
+            background: None,
+            refresh_timeline: None,
+            min_full_refresh_interval: Duration::from_secs(0),
+            last_full_refresh: None,
+            viewport: None,
+            auto_full_refresh_every: 0,
+            partial_refreshes_since_full: 0,
+            back_buffer: None,
+            update_log: Some(Arc::new(Mutex::new(Vec::new()))),
+            in_flight_updates: Vec::new(),
+            default_temp: display_temp::TEMP_USE_AMBIENT,
+            #[cfg(feature = "draw-stats")]
+            draw_stats: Default::default(),
+        }
+    }
+
+    /// Updates recorded so far by a framebuffer created with `in_memory`, oldest first.
+    /// Always empty for the framebuffer of the device.
+    pub fn submitted_updates(&self) -> Vec<mxcfb_update_data> {
+        match self.update_log {
+            Some(ref log) => log.lock().unwrap().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Sets the temperature that refreshes passed `TEMP_AUTO` are submitted with, including
+    /// those made through a `RefreshHandle` obtained afterwards. Defaults to `TEMP_USE_AMBIENT`,
+    /// which has the EPDC read the temperature sensor, and is reset to it when given
+    /// `TEMP_AUTO`.
+    pub fn set_default_temp(&mut self, temp: display_temp) {
+        self.default_temp = temp.resolve(display_temp::TEMP_USE_AMBIENT);
+    }
+
+    /// Returns the counters accumulated by the drawing functions since the previous call and
+    /// resets them. Only available with the `draw-stats` feature, without which nothing is
+    /// measured.
+    #[cfg(feature = "draw-stats")]
+    pub fn take_stats(&mut self) -> DrawStats {
+        ::std::mem::take(&mut self.draw_stats)
+    }
+
+    /// Loads the TrueType font in `bytes` for the `_font` variants of the text functions of
+    /// `FramebufferDraw`, e.g. `draw_text_font`. Fails if `bytes` isn't a font.
+    pub fn load_font(&mut self, bytes: &[u8]) -> Result<FontId, Error> {
+        match FontCollection::from_bytes(bytes.to_vec()).into_font() {
+            Some(font) => {
+                self.fonts.push(font);
+                Ok(FontId(self.fonts.len() - 1))
+            }
+            None => Err(Error::UnsupportedFormat(
+                "Unable to load the font, expected a single TrueType font".to_owned(),
+            )),
+        }
+    }
+
+    /// Resolves `font` to a loaded font, the default one when `None` or not loaded here
+    pub fn font(&self, font: Option<FontId>) -> &Font<'a> {
+        match font {
+            Some(FontId(index)) => self.fonts.get(index).unwrap_or(&self.default_font),
+            None => &self.default_font,
+        }
+    }
+
+    /// Visible resolution of the panel as `(xres, yres)`, as currently reported by the
+    /// driver rather than the compile-time `DISPLAYWIDTH`/`DISPLAYHEIGHT`
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.var_screen_info.xres, self.var_screen_info.yres)
+    }
+
+    /// Virtual resolution of the framebuffer as `(xres_virtual, yres_virtual)`
+    pub fn virtual_dimensions(&self) -> (u32, u32) {
+        (
+            self.var_screen_info.xres_virtual,
+            self.var_screen_info.yres_virtual,
+        )
+    }
+
+    /// Area that may be drawn to and refreshed: the viewport if set, otherwise the screen
+    pub(crate) fn drawable_rect(&self) -> mxcfb_rect {
+        match self.viewport {
+            Some(viewport) => viewport,
+            None => mxcfb_rect {
+                top: 0,
+                left: 0,
+                height: self.var_screen_info.yres,
+                width: self.var_screen_info.xres,
+            },
+        }
+    }
+
+    /// Start of the memory reads come from: the back buffer during a frame started with
+    /// `begin_frame`, otherwise the mapped framebuffer
+    pub(crate) fn frame_ptr(&self) -> *const u8 {
+        match self.back_buffer {
+            Some(ref buffer) => buffer.as_ptr(),
+            None => self.frame.data(),
+        }
+    }
+
+    /// Same as `frame_ptr`, for the memory draws go to
+    pub(crate) fn frame_ptr_mut(&mut self) -> *mut u8 {
+        match self.back_buffer {
+            Some(ref mut buffer) => buffer.as_mut_ptr(),
+            None => self.frame.data(),
+        }
+    }
+
+    /// Whether the pixel at `(y, x)` lies within `drawable_rect`
+    pub(crate) fn is_drawable(&self, y: usize, x: usize) -> bool {
+        let r = self.drawable_rect();
+        y >= r.top as usize
+            && x >= r.left as usize
+            && y < (r.top + r.height) as usize
+            && x < (r.left + r.width) as usize
+    }
+}
+
 impl<'a> framebuffer::FramebufferBase<'a> for Framebuffer<'a> {
     fn new(path_to_device: &str) -> Framebuffer {
         let device = OpenOptions::new()
@@ -54,10 +297,6 @@ impl<'a> framebuffer::FramebufferBase<'a> for Framebuffer<'a> {
             ],
         ).unwrap();
 
-        // Load the font
-        let font_data = include_bytes!("../../assets/DejaVuSans.ttf");
-        let collection = FontCollection::from_bytes(font_data as &[u8]);
-
         var_screen_info.xres = 1872;
         var_screen_info.yres = 1404;
         var_screen_info.rotate = 1;
@@ -74,16 +313,34 @@ impl<'a> framebuffer::FramebufferBase<'a> for Framebuffer<'a> {
         var_screen_info.vmode = 0; // FB_VMODE_NONINTERLACED
         var_screen_info.accel_flags = 0;
         let mut fb = Framebuffer {
-            marker: AtomicU32::new(1),
+            marker: Arc::new(AtomicU32::new(1)),
+            refresh_lock: Arc::new(Mutex::new(())),
             device,
             frame: mem_map,
-            default_font: collection.into_font().unwrap(),
+            default_font: builtin_font(),
+            fonts: Vec::new(),
             var_screen_info,
             fix_screen_info,
+            background: None,
+            refresh_timeline: None,
+            min_full_refresh_interval: Duration::from_secs(0),
+            last_full_refresh: None,
+            viewport: None,
+            auto_full_refresh_every: 0,
+            partial_refreshes_since_full: 0,
+            back_buffer: None,
+            update_log: None,
+            in_flight_updates: Vec::new(),
+            default_temp: display_temp::TEMP_USE_AMBIENT,
+            #[cfg(feature = "draw-stats")]
+            draw_stats: Default::default(),
         };
         if !fb.put_var_screeninfo() {
             panic!("FBIOPUT_VSCREENINFO failed");
         }
+        if let Err(e) = fb.verify_abi() {
+            error!("Unexpected framebuffer ABI, refreshes may misbehave: {0}", e);
+        }
         return fb;
     }
 
@@ -138,6 +395,70 @@ impl<'a> framebuffer::FramebufferBase<'a> for Framebuffer<'a> {
         return info;
     }
 
+    fn verify_abi(&self) -> Result<(), Error> {
+        let sizes = [
+            (
+                "mxcfb_update_data",
+                EXPECTED_UPDATE_DATA_SIZE,
+                ::std::mem::size_of::<mxcfb_update_data>(),
+            ),
+            (
+                "mxcfb_update_marker_data",
+                EXPECTED_UPDATE_MARKER_DATA_SIZE,
+                ::std::mem::size_of::<mxcfb_update_marker_data>(),
+            ),
+        ];
+        for &(name, expected, actual) in sizes.iter() {
+            if expected != actual {
+                return Err(Error::AbiMismatch(AbiMismatch::StructSize {
+                    name,
+                    expected,
+                    actual,
+                }));
+            }
+        }
+
+        let bytespp = self.var_screen_info.bits_per_pixel / 8;
+        if bytespp == 0 {
+            return Err(Error::AbiMismatch(AbiMismatch::ScreenInfo(format!(
+                "{0} bits per pixel",
+                self.var_screen_info.bits_per_pixel
+            ))));
+        }
+        if self.fix_screen_info.line_length < self.var_screen_info.xres * bytespp {
+            return Err(Error::AbiMismatch(AbiMismatch::ScreenInfo(format!(
+                "line_length of {0} is too short for {1}px wide rows",
+                self.fix_screen_info.line_length, self.var_screen_info.xres
+            ))));
+        }
+
+        Ok(())
+    }
+
+    fn probe_update_ioctls(&self) -> Result<(), Error> {
+        // mxc_epdc_fb looks the marker up in its list of pending updates and fails with
+        // EINVAL when it isn't there, without waiting. If the kernel rejects the ioctl number
+        // itself (ENOTTY), it must have been built for a differently sized
+        // mxcfb_update_marker_data.
+        let mut markerdata = mxcfb_update_marker_data {
+            update_marker: ABI_PROBE_MARKER,
+            collision_test: 0,
+        };
+        let result = unsafe {
+            ioctl(
+                self.device.as_raw_fd(),
+                MXCFB_WAIT_FOR_UPDATE_COMPLETE,
+                &mut markerdata,
+            )
+        };
+        if result < 0 && ::std::io::Error::last_os_error().raw_os_error() == Some(libc::ENOTTY) {
+            return Err(Error::AbiMismatch(AbiMismatch::UnknownIoctl {
+                name: "MXCFB_WAIT_FOR_UPDATE_COMPLETE",
+            }));
+        }
+        Ok(())
+    }
+
     fn put_var_screeninfo(&mut self) -> bool {
         let result = unsafe {
             ioctl(
@@ -148,4 +469,17 @@ impl<'a> framebuffer::FramebufferBase<'a> for Framebuffer<'a> {
         };
         return result == 0;
     }
+
+    fn set_viewport(&mut self, viewport: Option<mxcfb_rect>) {
+        self.viewport = None;
+        let screen = self.drawable_rect();
+        self.viewport = viewport.map(|v| {
+            v.intersection(&screen)
+                .unwrap_or_else(mxcfb_rect::invalid)
+        });
+    }
+
+    fn viewport(&self) -> Option<mxcfb_rect> {
+        self.viewport
+    }
 }
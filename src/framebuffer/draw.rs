@@ -1,15 +1,21 @@
 use std;
+use std::collections::HashSet;
+#[cfg(feature = "draw-stats")]
+use std::time::Instant;
 
 use libc;
+use cgmath;
+use image;
 use image::DynamicImage;
-use rusttype::{point, Scale};
-use line_drawing;
+use rusttype::{point, Font, GlyphId, Scale};
 use image::GenericImage;
 
 use framebuffer;
 use framebuffer::FramebufferIO;
 use framebuffer::common::*;
 use framebuffer::core;
+use framebuffer::layers::blend;
+use framebuffer::offscreen::Rotation;
 
 macro_rules! min {
         ($x: expr) => ($x);
@@ -21,6 +27,439 @@ macro_rules! max {
         ($x: expr, $($z: expr),+) => (::std::cmp::max($x, max!($($z),*)));
 }
 
+/// What a stroke leaves behind on the framebuffer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EraseOrColor {
+    /// Paints the stroke with the given color
+    Color(color),
+    /// Restores the captured background under the stroke, or paints white if there is none
+    Erase,
+    /// Darkens what is under the stroke by multiplying it with the given color, so that
+    /// strokes drawn over each other build up ink towards black like a real pen would. A
+    /// single stroke darkens every pixel it covers only once, however wide it is.
+    Accumulate(color),
+}
+
+/// Calls made to one of the drawing functions tracked by `DrawStats`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DrawOpStats {
+    pub calls: u32,
+    /// Area of the rects the calls returned, so the pixels they wrote or at most that
+    pub pixels: u64,
+    /// Wall-clock time spent in the calls
+    pub nanos: u64,
+}
+
+#[cfg(feature = "draw-stats")]
+impl DrawOpStats {
+    fn record(&mut self, started: Instant, rect: &mxcfb_rect) {
+        let elapsed = started.elapsed();
+        self.calls += 1;
+        self.pixels += rect.width as u64 * rect.height as u64;
+        self.nanos += elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    }
+}
+
+/// Counters the drawing functions accumulate when the `draw-stats` feature is enabled, see
+/// `Framebuffer::take_stats`. `fill_rect` includes `fill_rect_checked`, `draw_text` includes
+/// `draw_text_font` and `draw_image` is `draw_grayscale_image`, as used by `display_image`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DrawStats {
+    pub fill_rect: DrawOpStats,
+    pub draw_text: DrawOpStats,
+    pub draw_image: DrawOpStats,
+}
+
+/// Brightness, contrast and gamma adjustments applied to the luma of an image before it is
+/// drawn. Photos tend to look washed out on the panel and a gamma above 1.0 makes their
+/// mid-tones readable. `ImageAdjust::default()` leaves the image untouched.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageAdjust {
+    /// Added to every level, from -1.0 (everything black) to 1.0 (everything white)
+    pub brightness: f32,
+    /// Scales the distance of every level from mid-gray, 1.0 is neutral
+    pub contrast: f32,
+    /// Levels are raised to the power of `1 / gamma`, 1.0 is neutral and larger values
+    /// brighten the mid-tones
+    pub gamma: f32,
+}
+
+impl Default for ImageAdjust {
+    fn default() -> ImageAdjust {
+        ImageAdjust {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl ImageAdjust {
+    /// Builds the lookup table mapping every input level to its adjusted level
+    pub fn lookup_table(&self) -> [u8; 256] {
+        let mut table = [0u8; 256];
+        let inv_gamma = if self.gamma > 0.0 { 1.0 / self.gamma } else { 1.0 };
+        for (level, entry) in table.iter_mut().enumerate() {
+            let v = level as f32 / 255.0;
+            let v = (v - 0.5) * self.contrast + 0.5 + self.brightness;
+            let v = v.max(0.0).min(1.0).powf(inv_gamma);
+            *entry = (v * 255.0).round() as u8;
+        }
+        table
+    }
+
+    /// Returns a grayscale copy of `img` with the adjustments applied
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let table = self.lookup_table();
+        let mut luma = img.to_luma();
+        for pixel in luma.pixels_mut() {
+            pixel.data[0] = table[pixel.data[0] as usize];
+        }
+        DynamicImage::ImageLuma8(luma)
+    }
+}
+
+/// Size of `img` once scaled by `scale` as `transform_image` does, before turning it
+fn scaled_size(img: &DynamicImage, scale: f32) -> (u32, u32) {
+    let height = ((img.height() as f32 * scale).round() as u32).max(1);
+    let width = ((img.width() as f32 * scale).round() as u32).max(1);
+    (height, width)
+}
+
+/// Returns the height and width of `transform_image(img, scale, rotation)` without
+/// transforming anything
+pub fn transformed_size(img: &DynamicImage, scale: f32, rotation: Rotation) -> (u32, u32) {
+    let (height, width) = scaled_size(img, scale);
+    match rotation {
+        Rotation::None | Rotation::UpsideDown => (height, width),
+        Rotation::Clockwise90 | Rotation::CounterClockwise90 => (width, height),
+    }
+}
+
+/// Returns a copy of `img` scaled by `scale` with nearest-neighbor sampling, then turned
+/// by `rotation`. Neither side of the result is ever shorter than a pixel.
+pub fn transform_image(img: &DynamicImage, scale: f32, rotation: Rotation) -> DynamicImage {
+    let scaled = match scale == 1.0 {
+        true => img.clone(),
+        false => {
+            let (height, width) = scaled_size(img, scale);
+            img.resize_exact(width, height, image::FilterType::Nearest)
+        }
+    };
+    match rotation {
+        Rotation::None => scaled,
+        Rotation::Clockwise90 => scaled.rotate90(),
+        Rotation::UpsideDown => scaled.rotate180(),
+        Rotation::CounterClockwise90 => scaled.rotate270(),
+    }
+}
+
+/// Identifies a font loaded with `Framebuffer::load_font`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FontId(pub(crate) usize);
+
+/// Where `draw_text_aligned` puts a text within the span it is given
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// Axis along which `fill_rect_gradient` goes from its first to its second color
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GradientDirection {
+    /// Left to right
+    Horizontal,
+    /// Top to bottom
+    Vertical,
+}
+
+/// Number of gray levels the panel tells apart
+const GRADIENT_LEVELS: f32 = 16.0;
+
+/// 4x4 Bayer matrix ordering the dithering thresholds of `fill_rect_gradient`
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Software dithering applied by `draw_dithered_image` when reducing an image to the gray
+/// levels of the panel, which avoids the banding of the EPDC passthrough dithering
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DitherAlgorithm {
+    /// Rounds every pixel to the nearest level
+    None,
+    /// Diffuses the whole quantization error to the neighbors not visited yet
+    FloydSteinberg,
+    /// Offsets every pixel by the 4x4 Bayer matrix before rounding
+    Ordered,
+    /// Diffuses three quarters of the error over a wider neighborhood, which keeps more
+    /// contrast than Floyd-Steinberg at the cost of losing detail in the extremes
+    Atkinson,
+}
+
+/// Rounds `v`, a level between 0.0 and 255.0, to the nearest of the `GRADIENT_LEVELS`
+fn quantize_level(v: f32) -> u8 {
+    let step = 255.0 / (GRADIENT_LEVELS - 1.0);
+    ((v / step).round().max(0.0).min(GRADIENT_LEVELS - 1.0) * step) as u8
+}
+
+/// Reduces the row-major `luma` of an image `width` pixels wide to `GRADIENT_LEVELS`
+pub fn dither_luma(luma: &mut [u8], width: usize, algorithm: DitherAlgorithm) {
+    if width == 0 {
+        return;
+    }
+    let height = luma.len() / width;
+    // Offsets and weights of the neighbors that receive part of the error
+    let diffusion: &[(isize, usize, f32)] = match algorithm {
+        DitherAlgorithm::FloydSteinberg => &[
+            (1, 0, 7.0 / 16.0),
+            (-1, 1, 3.0 / 16.0),
+            (0, 1, 5.0 / 16.0),
+            (1, 1, 1.0 / 16.0),
+        ],
+        DitherAlgorithm::Atkinson => &[
+            (1, 0, 1.0 / 8.0),
+            (2, 0, 1.0 / 8.0),
+            (-1, 1, 1.0 / 8.0),
+            (0, 1, 1.0 / 8.0),
+            (1, 1, 1.0 / 8.0),
+            (0, 2, 1.0 / 8.0),
+        ],
+        DitherAlgorithm::None | DitherAlgorithm::Ordered => &[],
+    };
+
+    let mut levels: Vec<f32> = luma.iter().map(|&l| l as f32).collect();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let v = match algorithm {
+                DitherAlgorithm::Ordered => {
+                    let threshold = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+                    levels[i] + threshold * 255.0 / (GRADIENT_LEVELS - 1.0)
+                }
+                _ => levels[i],
+            };
+            let out = quantize_level(v);
+            luma[i] = out;
+
+            let error = v - out as f32;
+            for &(dx, dy, weight) in diffusion {
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx < 0 || nx >= width as isize || ny >= height {
+                    continue;
+                }
+                levels[ny * width + nx as usize] += error * weight;
+            }
+        }
+    }
+}
+
+/// Upper bound on the number of pixels `is_bilevel_image` and `auto_refresh` sample
+pub const BILEVEL_MAX_SAMPLES: usize = 1024;
+
+/// Fraction of the sampled pixels that need to be near black or white for the content to
+/// be considered bilevel
+pub const BILEVEL_RATIO: f32 = 0.95;
+
+/// Levels within this distance of 0x00 or 0xFF count as black or white
+pub const BILEVEL_EXTREME_MARGIN: u8 = 0x20;
+
+/// Smallest stride such that a grid over `height` by `width` pixels has at most
+/// `BILEVEL_MAX_SAMPLES` points
+pub(crate) fn bilevel_sample_stride(height: usize, width: usize) -> usize {
+    let area = width * height;
+    let mut stride = 1;
+    while area / (stride * stride) > BILEVEL_MAX_SAMPLES {
+        stride += 1;
+    }
+    stride
+}
+
+/// Reports whether nearly all of the sampled gray `levels` are close to black or white, as
+/// in text and UI. No samples at all count as bilevel.
+pub fn is_bilevel<I: IntoIterator<Item = u8>>(levels: I) -> bool {
+    let (mut sampled, mut extremes) = (0usize, 0usize);
+    for level in levels {
+        if level <= BILEVEL_EXTREME_MARGIN || level >= 0xFF - BILEVEL_EXTREME_MARGIN {
+            extremes += 1;
+        }
+        sampled += 1;
+    }
+    extremes as f32 >= sampled as f32 * BILEVEL_RATIO
+}
+
+/// Samples the histogram of `img` on an evenly spaced grid and reports whether it is
+/// bilevel according to `is_bilevel`, as screenshots of text and UI are
+pub fn is_bilevel_image(img: &DynamicImage) -> bool {
+    let luma = img.to_luma();
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    let stride = bilevel_sample_stride(height, width);
+    let luma = &luma;
+    is_bilevel((0..height).step_by(stride).flat_map(move |y| {
+        (0..width)
+            .step_by(stride)
+            .map(move |x| luma.get_pixel(x as u32, y as u32).data[0])
+    }))
+}
+
+/// Bounding rect of the circle of `radius` around `center`, clipped to what can be drawn
+fn circle_bounds(fb: &core::Framebuffer, center: cgmath::Point2<i32>, radius: u32) -> mxcfb_rect {
+    let r = radius as i32;
+    let (top, left) = (max!(center.y - r, 0), max!(center.x - r, 0));
+    let (bottom, right) = (center.y + r + 1, center.x + r + 1);
+    if bottom <= top || right <= left {
+        return mxcfb_rect::invalid();
+    }
+    let rect = mxcfb_rect {
+        top: top as u32,
+        left: left as u32,
+        height: (bottom - top) as u32,
+        width: (right - left) as u32,
+    };
+    rect.intersection(&fb.drawable_rect())
+        .unwrap_or_else(mxcfb_rect::invalid)
+}
+
+/// Bounding rect of `points` grown by `pad` on every side, clipped to what can be drawn
+fn points_bounds(fb: &core::Framebuffer, points: &[cgmath::Point2<i32>], pad: i32) -> mxcfb_rect {
+    if points.is_empty() {
+        return mxcfb_rect::invalid();
+    }
+    let (mut top, mut left) = (i32::max_value(), i32::max_value());
+    let (mut bottom, mut right) = (i32::min_value(), i32::min_value());
+    for p in points {
+        top = min!(top, p.y);
+        left = min!(left, p.x);
+        bottom = max!(bottom, p.y);
+        right = max!(right, p.x);
+    }
+    let rect = mxcfb_rect::from_corners(
+        cgmath::Point2 {
+            x: left.saturating_sub(pad),
+            y: top.saturating_sub(pad),
+        },
+        cgmath::Point2 {
+            x: right.saturating_add(pad + 1),
+            y: bottom.saturating_add(pad + 1),
+        },
+    );
+    rect.intersection(&fb.drawable_rect())
+        .unwrap_or_else(mxcfb_rect::invalid)
+}
+
+/// Fills the disc of `radius` around the sub-pixel `center`, clipped to the drawable area.
+/// Returns the rect that was written.
+fn fill_disc(
+    fb: &mut core::Framebuffer,
+    center: cgmath::Point2<f32>,
+    radius: f32,
+    v: color,
+) -> mxcfb_rect {
+    let bounds = fb.drawable_rect();
+    let top = (center.y - radius).floor().max(bounds.top as f32);
+    let left = (center.x - radius).floor().max(bounds.left as f32);
+    let bottom = ((center.y + radius).ceil() + 1.0).min((bounds.top + bounds.height) as f32);
+    let right = ((center.x + radius).ceil() + 1.0).min((bounds.left + bounds.width) as f32);
+    if bottom <= top || right <= left {
+        return mxcfb_rect::invalid();
+    }
+
+    let (top, left, bottom, right) = (top as u32, left as u32, bottom as u32, right as u32);
+    let r2 = radius * radius;
+    for y in top..bottom {
+        let dy = y as f32 - center.y;
+        for x in left..right {
+            let dx = x as f32 - center.x;
+            if dx * dx + dy * dy <= r2 {
+                fb.write_pixel(y as usize, x as usize, v);
+            }
+        }
+    }
+    mxcfb_rect {
+        top,
+        left,
+        height: bottom - top,
+        width: right - left,
+    }
+}
+
+/// Same as `FramebufferDraw::measure_glyphs` but laid out with `font`
+fn glyph_rects(font: &Font, y: usize, x: usize, text: &str, size: usize) -> Vec<mxcfb_rect> {
+    let scale = Scale {
+        x: size as f32,
+        y: size as f32,
+    };
+    let v_metrics = font.v_metrics(scale);
+    let top = (y as f32 - v_metrics.ascent).max(0.0) as u32;
+    let bottom = (y as f32 - v_metrics.descent).ceil().max(0.0) as u32;
+
+    font.layout(text, scale, point(x as f32, y as f32))
+        .map(|glyph| {
+            let left = glyph.position().x.max(0.0);
+            let advance = glyph.unpositioned().h_metrics().advance_width;
+            mxcfb_rect {
+                top,
+                left: left as u32,
+                height: bottom - top,
+                width: ((left + advance).ceil() as u32).saturating_sub(left as u32),
+            }
+        })
+        .collect()
+}
+
+/// End of a line laid out so far, which `advanced` moves on the way `Font::layout` would
+/// when laying out the whole line, kerning included
+#[derive(Copy, Clone)]
+struct Caret {
+    x: f32,
+    last: Option<GlyphId>,
+}
+
+impl Caret {
+    fn advanced_char(self, font: &Font, scale: Scale, ch: char) -> Caret {
+        let glyph = font.glyph(ch).unwrap().scaled(scale);
+        let kerning = match self.last {
+            Some(last) => font.pair_kerning(scale, last, glyph.id()),
+            None => 0.0,
+        };
+        Caret {
+            x: self.x + kerning + glyph.h_metrics().advance_width,
+            last: Some(glyph.id()),
+        }
+    }
+
+    fn advanced(self, font: &Font, scale: Scale, text: &str) -> Caret {
+        text.chars().fold(self, |caret, ch| caret.advanced_char(font, scale, ch))
+    }
+
+    /// Width from the start of the line to the end of the advance of its last character, as
+    /// `measure_glyphs` would measure it
+    fn width(&self) -> usize {
+        self.x.max(0.0).ceil() as usize
+    }
+}
+
+/// Puts the pixel at `(y, x)` back to its value in the captured background
+fn erase_pixel(fb: &mut core::Framebuffer, y: usize, x: usize) {
+    if !fb.is_drawable(y, x) {
+        return;
+    }
+    let line_length = fb.fix_screen_info.line_length as usize;
+    let bytespp = (fb.var_screen_info.bits_per_pixel / 8) as usize;
+    let curr_index = y * line_length + x * bytespp;
+
+    let begin = fb.frame_ptr_mut();
+    match fb.background {
+        Some(ref background) => {
+            for i in 0..bytespp {
+                unsafe {
+                    *(begin.offset((curr_index + i) as isize)) = background[curr_index + i];
+                }
+            }
+        }
+        None => fb.write_pixel(y, x, color::WHITE),
+    }
+}
+
 /// Helper function to sample pixels on the bezier curve.
 fn sample_bezier(startpt: (f32, f32), ctrlpt: (f32, f32), endpt: (f32, f32)) -> Vec<(f32, f32)> {
     let mut points = Vec::new();
@@ -45,6 +484,8 @@ fn sample_bezier(startpt: (f32, f32), ctrlpt: (f32, f32), endpt: (f32, f32)) ->
 
 impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
     fn draw_grayscale_image(&mut self, img: &DynamicImage, top: usize, left: usize) -> mxcfb_rect {
+        #[cfg(feature = "draw-stats")]
+        let started = Instant::now();
         for (x, y, pixel) in img.to_luma().enumerate_pixels() {
             self.write_pixel(
                 top + y as usize,
@@ -52,6 +493,27 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
                 color::GRAY(pixel.data[0]),
             );
         }
+        let rect = mxcfb_rect {
+            top: top as u32,
+            left: left as u32,
+            width: img.width(),
+            height: img.height(),
+        };
+        #[cfg(feature = "draw-stats")]
+        self.draw_stats.draw_image.record(started, &rect);
+        return rect;
+    }
+
+    fn draw_image_alpha(&mut self, img: &DynamicImage, top: usize, left: usize) -> mxcfb_rect {
+        for (x, y, pixel) in img.to_luma_alpha().enumerate_pixels() {
+            let (py, px) = (top + y as usize, left + x as usize);
+            let (level, alpha) = (pixel.data[0], pixel.data[1]);
+            if alpha == 0 || !self.is_drawable(py, px) {
+                continue;
+            }
+            let under = self.read_pixel(py, px).as_native()[0];
+            self.write_pixel(py, px, color::GRAY(blend(under, level, alpha)));
+        }
         return mxcfb_rect {
             top: top as u32,
             left: left as u32,
@@ -60,6 +522,57 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         };
     }
 
+    fn draw_bilevel_image(&mut self, img: &DynamicImage, top: usize, left: usize) -> mxcfb_rect {
+        let levels: Vec<u8> = img.to_luma()
+            .into_raw()
+            .into_iter()
+            .map(|level| match level < 0x80 {
+                true => 0x00,
+                false => 0xFF,
+            })
+            .collect();
+        self.write_luma(
+            mxcfb_rect {
+                top: top as u32,
+                left: left as u32,
+                width: img.width(),
+                height: img.height(),
+            },
+            &levels,
+        )
+    }
+
+    fn draw_dithered_image(
+        &mut self,
+        img: &DynamicImage,
+        top: usize,
+        left: usize,
+        algorithm: DitherAlgorithm,
+    ) -> mxcfb_rect {
+        let rect = mxcfb_rect {
+            top: top as u32,
+            left: left as u32,
+            width: img.width(),
+            height: img.height(),
+        };
+        let visible = match rect.intersection(&self.drawable_rect()) {
+            Some(r) => r,
+            None => return mxcfb_rect::invalid(),
+        };
+
+        // Only the part that ends up on screen is dithered
+        let luma = img.to_luma();
+        let (skip_rows, skip_cols) = (visible.top - rect.top, visible.left - rect.left);
+        let mut levels = Vec::with_capacity((visible.width * visible.height) as usize);
+        for y in skip_rows..skip_rows + visible.height {
+            for x in skip_cols..skip_cols + visible.width {
+                levels.push(luma.get_pixel(x, y).data[0]);
+            }
+        }
+        dither_luma(&mut levels, visible.width as usize, algorithm);
+        self.write_luma(visible, &levels)
+    }
+
     fn draw_line(
         &mut self,
         y0: i32,
@@ -68,6 +581,18 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         x1: i32,
         width: usize,
         v: color,
+    ) -> mxcfb_rect {
+        self.draw_line_ink(y0, x0, y1, x1, width, EraseOrColor::Color(v))
+    }
+
+    fn draw_line_ink(
+        &mut self,
+        y0: i32,
+        x0: i32,
+        y1: i32,
+        x1: i32,
+        width: usize,
+        ink: EraseOrColor,
     ) -> mxcfb_rect {
         // Create local variables for moving start point
         let mut x0 = x0;
@@ -86,17 +611,36 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         let mut err2;
 
         let (mut min_x, mut max_x, mut min_y, mut max_y) = (x0, x0, y0, y0);
+        // Pixels covered by an accumulating stroke, inked once it is complete
+        let mut accumulated: HashSet<(i32, i32)> = HashSet::new();
         loop {
             // Set pixel
-            match width {
-                1 => self.write_pixel(y0 as usize, x0 as usize, v),
-                _ => self.fill_rect(
+            match (width, ink) {
+                (_, EraseOrColor::Accumulate(_)) => {
+                    let (top, left) = (y0 - (width / 2) as i32, x0 - (width / 2) as i32);
+                    for ypos in top..top + max!(width, 1) as i32 {
+                        for xpos in left..left + max!(width, 1) as i32 {
+                            accumulated.insert((ypos, xpos));
+                        }
+                    }
+                }
+                (1, EraseOrColor::Color(v)) => self.write_pixel(y0 as usize, x0 as usize, v),
+                (1, EraseOrColor::Erase) => erase_pixel(self, y0 as usize, x0 as usize),
+                (_, EraseOrColor::Color(v)) => self.fill_rect(
                     (y0 - (width / 2) as i32) as usize,
                     (x0 - (width / 2) as i32) as usize,
                     width,
                     width,
                     v,
                 ),
+                (_, EraseOrColor::Erase) => {
+                    let (top, left) = (y0 - (width / 2) as i32, x0 - (width / 2) as i32);
+                    for ypos in top..top + width as i32 {
+                        for xpos in left..left + width as i32 {
+                            erase_pixel(self, ypos as usize, xpos as usize);
+                        }
+                    }
+                }
             }
 
             max_y = max!(max_y, y0);
@@ -123,6 +667,31 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
             }
         }
 
+        if let EraseOrColor::Accumulate(v) = ink {
+            let ink = v.as_native();
+            for (ypos, xpos) in accumulated {
+                if ypos < 0 || xpos < 0 {
+                    continue;
+                }
+                let (ypos, xpos) = (ypos as usize, xpos as usize);
+                if ypos >= self.var_screen_info.yres as usize
+                    || xpos >= self.var_screen_info.xres as usize
+                {
+                    continue;
+                }
+                let under = self.read_pixel(ypos, xpos).as_native();
+                let mut darkened = [0u8; 4];
+                for (d, (&u, &i)) in darkened.iter_mut().zip(under.iter().zip(&ink)) {
+                    *d = (u as u32 * i as u32 / 0xFF) as u8;
+                }
+                self.write_pixel(
+                    ypos,
+                    xpos,
+                    color::NATIVE_COMPONENTS(darkened[0], darkened[1], darkened[2], darkened[3]),
+                );
+            }
+        }
+
         return mxcfb_rect {
             top: min_y as u32,
             left: min_x as u32,
@@ -131,30 +700,198 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         };
     }
 
-    fn draw_circle(&mut self, y: usize, x: usize, rad: usize, v: color) -> mxcfb_rect {
-        for (x, y) in line_drawing::BresenhamCircle::new(x as i32, y as i32, rad as i32) {
-            self.write_pixel(y as usize, x as usize, v);
+    fn capture_background(&mut self) {
+        let length = (self.fix_screen_info.line_length * self.var_screen_info.yres) as usize;
+        let frame = unsafe { std::slice::from_raw_parts(self.frame_ptr(), length) };
+        self.background = Some(frame.to_vec());
+    }
+
+    fn release_background(&mut self) {
+        self.background = None;
+    }
+
+    fn draw_points(&mut self, points: &[cgmath::Point2<i32>], v: color) -> mxcfb_rect {
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        let components = v.as_native();
+        let begin = self.frame_ptr_mut();
+
+        let (mut top, mut left, mut bottom, mut right) = (u32::max_value(), u32::max_value(), 0, 0);
+        for p in points {
+            if p.x < 0 || p.y < 0 || !self.is_drawable(p.y as usize, p.x as usize) {
+                continue;
+            }
+            let (y, x) = (p.y as u32, p.x as u32);
+            let curr_index = (y as usize * line_length + x as usize * bytespp) as isize;
+            unsafe {
+                *(begin.offset(curr_index)) = components[0];
+                *(begin.offset(curr_index + 1)) = components[1];
+                *(begin.offset(curr_index + 2)) = components[2];
+                *(begin.offset(curr_index + 3)) = components[3];
+            }
+            top = min!(top, y);
+            left = min!(left, x);
+            bottom = max!(bottom, y + 1);
+            right = max!(right, x + 1);
         }
-        return mxcfb_rect {
-            top: y as u32 - rad as u32,
-            left: x as u32 - rad as u32,
-            width: 2 * rad as u32,
-            height: 2 * rad as u32,
+
+        match bottom > top {
+            true => mxcfb_rect {
+                top,
+                left,
+                height: bottom - top,
+                width: right - left,
+            },
+            false => mxcfb_rect::invalid(),
+        }
+    }
+
+    fn draw_polyline(
+        &mut self,
+        points: &[cgmath::Point2<i32>],
+        width: usize,
+        v: color,
+    ) -> mxcfb_rect {
+        if points.len() == 1 {
+            let p = points[0];
+            self.draw_line(p.y, p.x, p.y, p.x, width, v);
+        }
+        for segment in points.windows(2) {
+            let (from, to) = (segment[0], segment[1]);
+            self.draw_line(from.y, from.x, to.y, to.x, width, v);
+        }
+        points_bounds(self, points, (width / 2) as i32)
+    }
+
+    fn fill_polygon(&mut self, points: &[cgmath::Point2<i32>], v: color) -> mxcfb_rect {
+        if points.len() < 3 {
+            return mxcfb_rect::invalid();
+        }
+        let bounds = points_bounds(self, points, 0);
+        if bounds == mxcfb_rect::invalid() {
+            return bounds;
+        }
+
+        // Scanline fill with the even-odd rule, sampling every row and column at the center
+        // of its pixels. Each row is filled between pairs of crossings of the outline.
+        let (right_edge, left_edge) = ((bounds.left + bounds.width) as i32, bounds.left as i32);
+        let mut crossings: Vec<f32> = Vec::new();
+        for y in bounds.top..bounds.top + bounds.height {
+            let yc = y as f32 + 0.5;
+            crossings.clear();
+            for i in 0..points.len() {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                let (ay, by) = (a.y as f32, b.y as f32);
+                if (ay > yc) != (by > yc) {
+                    crossings.push(a.x as f32 + (yc - ay) * (b.x - a.x) as f32 / (by - ay));
+                }
+            }
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for span in crossings.chunks(2) {
+                if span.len() < 2 {
+                    continue;
+                }
+                let from = max!((span[0] - 0.5).ceil() as i32, left_edge);
+                let to = min!((span[1] - 0.5).ceil() as i32, right_edge);
+                for x in from..to {
+                    self.write_pixel(y as usize, x as usize, v);
+                }
+            }
+        }
+        bounds
+    }
+
+    fn draw_circle(&mut self, center: cgmath::Point2<i32>, radius: u32, v: color) -> mxcfb_rect {
+        let (cx, cy, r) = (center.x, center.y, radius as i32);
+        let mut plot = |dx: i32, dy: i32| {
+            for &(x, y) in [
+                (cx + dx, cy + dy),
+                (cx - dx, cy + dy),
+                (cx + dx, cy - dy),
+                (cx - dx, cy - dy),
+                (cx + dy, cy + dx),
+                (cx - dy, cy + dx),
+                (cx + dy, cy - dx),
+                (cx - dy, cy - dx),
+            ].iter()
+            {
+                if x >= 0 && y >= 0 {
+                    self.write_pixel(y as usize, x as usize, v);
+                }
+            }
         };
+
+        // Midpoint circle algorithm, walking the first octant and mirroring it
+        let (mut dx, mut dy, mut err) = (r, 0, 1 - r);
+        while dx >= dy {
+            plot(dx, dy);
+            dy += 1;
+            match err < 0 {
+                true => err += 2 * dy + 1,
+                false => {
+                    dx -= 1;
+                    err += 2 * (dy - dx) + 1;
+                }
+            }
+        }
+        circle_bounds(self, center, radius)
     }
 
-    fn fill_circle(&mut self, y: usize, x: usize, rad: usize, v: color) -> mxcfb_rect {
-        for current in { 1..rad + 1 } {
-            for (x, y) in line_drawing::BresenhamCircle::new(x as i32, y as i32, current as i32) {
+    fn fill_circle(&mut self, center: cgmath::Point2<i32>, radius: u32, v: color) -> mxcfb_rect {
+        let bounds = self.drawable_rect();
+        let (left_edge, right_edge) = (bounds.left as i32, (bounds.left + bounds.width) as i32);
+        let r = radius as i32;
+        for dy in -r..r + 1 {
+            let y = center.y + dy;
+            if y < 0 {
+                continue;
+            }
+            // Filled with one horizontal span per row, clipped before writing anything
+            let half = ((r * r - dy * dy) as f32).sqrt() as i32;
+            let from = max!(center.x - half, left_edge);
+            let to = min!(center.x + half + 1, right_edge);
+            for x in from..to {
                 self.write_pixel(y as usize, x as usize, v);
             }
         }
-        return mxcfb_rect {
-            top: y as u32 - rad as u32,
-            left: x as u32 - rad as u32,
-            width: 2 * rad as u32,
-            height: 2 * rad as u32,
+        circle_bounds(self, center, radius)
+    }
+
+    fn draw_dynamic_stroke(
+        &mut self,
+        segments: &[(cgmath::Point2<f32>, f32)],
+        base_width: f32,
+        v: color,
+    ) -> mxcfb_rect {
+        // Never thinner than a pixel so that light touches still leave a trace
+        let radius = |pressure: f32| (base_width * pressure.max(0.0).min(1.0)).max(1.0) / 2.0;
+        let mut drawn: Option<mxcfb_rect> = None;
+        let mut stamp = |fb: &mut core::Framebuffer, center, r| {
+            let rect = fill_disc(fb, center, r, v);
+            if rect.width > 0 && rect.height > 0 {
+                drawn = Some(drawn.map_or(rect, |d| d.union(&rect)));
+            }
         };
+
+        if let Some(&(only, pressure)) = segments.first() {
+            stamp(self, only, radius(pressure));
+        }
+        for pair in segments.windows(2) {
+            let ((from, p0), (to, p1)) = (pair[0], pair[1]);
+            let (r0, r1) = (radius(p0), radius(p1));
+            // Discs stamped at most half a pixel apart sweep the whole area between samples
+            let length = ((to.x - from.x).powi(2) + (to.y - from.y).powi(2)).sqrt();
+            let steps = max!((length * 2.0).ceil() as usize, 1);
+            for i in 1..steps + 1 {
+                let t = i as f32 / steps as f32;
+                let center = cgmath::Point2::new(
+                    from.x + (to.x - from.x) * t,
+                    from.y + (to.y - from.y) * t,
+                );
+                stamp(self, center, r0 + (r1 - r0) * t);
+            }
+        }
+        drawn.unwrap_or_else(mxcfb_rect::invalid)
     }
 
     fn draw_bezier(
@@ -190,6 +927,20 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         size: usize,
         col: color,
     ) -> mxcfb_rect {
+        self.draw_text_font(y, x, &text, size, col, None)
+    }
+
+    fn draw_text_font(
+        &mut self,
+        y: usize,
+        x: usize,
+        text: &str,
+        size: usize,
+        col: color,
+        font: Option<FontId>,
+    ) -> mxcfb_rect {
+        #[cfg(feature = "draw-stats")]
+        let started = Instant::now();
         let scale = Scale {
             x: size as f32,
             y: size as f32,
@@ -198,7 +949,7 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         // The starting positioning of the glyphs (top left corner)
         let start = point(x as f32, y as f32);
 
-        let dfont = &mut self.default_font.clone();
+        let dfont = self.font(font).clone();
 
         let mut min_y = y;
         let mut max_y = y;
@@ -206,13 +957,11 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         let mut max_x = x;
 
         let components = col.as_native();
-        let c1 = (255 - components[0]) as f32;
-        let c2 = (255 - components[1]) as f32;
-        let c3 = (255 - components[2]) as f32;
-        let c4 = (255 - components[3]) as f32;
+        let xres = self.var_screen_info.xres as usize;
+        let yres = self.var_screen_info.yres as usize;
 
         // Loop through the glyphs in the text, positing each one on a line
-        for glyph in dfont.layout(&text, scale, start) {
+        for glyph in dfont.layout(text, scale, start) {
             if let Some(bounding_box) = glyph.pixel_bounding_box() {
                 // Draw the glyph into the image per-pixel by using the draw closure
                 let bbmax_y = bounding_box.max.y as usize;
@@ -231,22 +980,119 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
                 if bbmin_x < min_x {
                     min_x = bbmin_x;
                 }
+                // Each pixel of the glyph is blended with what is already on the framebuffer
+                // by its coverage so that the text can be drawn over any background
                 glyph.draw(|x, y, v| {
-                    let mult = (1.0 - v).min(1.0);
-                    self.write_pixel(
+                    let (py, px) = (
                         (y + bounding_box.min.y as u32) as usize,
                         (x + bounding_box.min.x as u32) as usize,
-                        color::NATIVE_COMPONENTS(
-                            (c1 * mult) as u8,
-                            (c2 * mult) as u8,
-                            (c3 * mult) as u8,
-                            (c4 * mult) as u8,
-                        ),
+                    );
+                    let coverage = v.max(0.0).min(1.0);
+                    if coverage == 0.0 || py >= yres || px >= xres {
+                        return;
+                    }
+                    let under = self.read_pixel(py, px).as_native();
+                    let mut blended = [0u8; 4];
+                    for (b, (&u, &c)) in blended.iter_mut().zip(under.iter().zip(&components)) {
+                        *b = (u as f32 * (1.0 - coverage) + c as f32 * coverage) as u8;
+                    }
+                    self.write_pixel(
+                        py,
+                        px,
+                        color::NATIVE_COMPONENTS(blended[0], blended[1], blended[2], blended[3]),
                     )
                 });
             }
         }
         // return the height and width of the drawn text so that refresh can be called on it
+        let rect = mxcfb_rect {
+            top: min_y as u32,
+            left: min_x as u32,
+            height: (max_y - min_y) as u32,
+            width: (max_x - min_x) as u32,
+        };
+        #[cfg(feature = "draw-stats")]
+        self.draw_stats.draw_text.record(started, &rect);
+        return rect;
+    }
+
+    fn draw_text_aa(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: color,
+        scale: usize,
+        text: &str,
+    ) -> mxcfb_rect {
+        let rect = self.measure_text(y, x, text, scale);
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        if width == 0 || height == 0 {
+            return rect;
+        }
+
+        // Coverage of the whole text first, so that overlapping glyphs don't blend twice
+        let mut coverage = vec![0f32; width * height];
+        let dfont = self.default_font.clone();
+        for glyph in dfont.layout(text, Scale::uniform(scale as f32), point(x as f32, y as f32)) {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, v| {
+                    let py = gy as i32 + bounding_box.min.y - rect.top as i32;
+                    let px = gx as i32 + bounding_box.min.x - rect.left as i32;
+                    if py >= 0 && px >= 0 && (py as usize) < height && (px as usize) < width {
+                        let cell = &mut coverage[py as usize * width + px as usize];
+                        *cell = cell.max(v.max(0.0).min(1.0));
+                    }
+                });
+            }
+        }
+
+        let target = c.as_native()[0] as f32;
+        for (i, &v) in coverage.iter().enumerate() {
+            let (py, px) = (rect.top as usize + i / width, rect.left as usize + i % width);
+            if v == 0.0 || !self.is_drawable(py, px) {
+                continue;
+            }
+            let under = self.read_pixel(py, px).as_native()[0] as f32;
+            let level = quantize_level(under * (1.0 - v) + target * v);
+            self.write_pixel(py, px, color::GRAY(level));
+        }
+        rect
+    }
+
+    fn measure_text(&self, y: usize, x: usize, text: &str, size: usize) -> mxcfb_rect {
+        self.measure_text_font(y, x, text, size, None)
+    }
+
+    fn measure_text_origin(&self, text: &str, scale: usize) -> (mxcfb_rect, usize) {
+        // Measured with the baseline one scale below the top so that no glyph ends up at a
+        // negative coordinate
+        let measured = self.measure_text(scale, 0, text, scale);
+        (measured, scale.saturating_sub(measured.top as usize))
+    }
+
+    fn measure_text_font(
+        &self,
+        y: usize,
+        x: usize,
+        text: &str,
+        size: usize,
+        font: Option<FontId>,
+    ) -> mxcfb_rect {
+        let scale = Scale {
+            x: size as f32,
+            y: size as f32,
+        };
+        let start = point(x as f32, y as f32);
+
+        let (mut min_y, mut max_y, mut min_x, mut max_x) = (y, y, x, x);
+        for glyph in self.font(font).layout(text, scale, start) {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                max_y = max!(max_y, bounding_box.max.y as usize);
+                max_x = max!(max_x, bounding_box.max.x as usize);
+                min_y = min!(min_y, bounding_box.min.y as usize);
+                min_x = min!(min_x, bounding_box.min.x as usize);
+            }
+        }
         return mxcfb_rect {
             top: min_y as u32,
             left: min_x as u32,
@@ -255,20 +1101,563 @@ impl<'a> framebuffer::FramebufferDraw for core::Framebuffer<'a> {
         };
     }
 
+    fn measure_glyphs(&self, y: usize, x: usize, text: &str, size: usize) -> Vec<mxcfb_rect> {
+        glyph_rects(&self.default_font, y, x, text, size)
+    }
+
+    fn draw_text_aligned(
+        &mut self,
+        y: usize,
+        x_region: usize,
+        width: usize,
+        align: TextAlign,
+        c: color,
+        scale: usize,
+        text: &str,
+    ) -> mxcfb_rect {
+        let text_width = match self.measure_glyphs(y, x_region, text, scale).last() {
+            Some(last) => ((last.left + last.width) as usize).saturating_sub(x_region),
+            None => 0,
+        };
+        let offset = match align {
+            TextAlign::Left => 0,
+            TextAlign::Center => width.saturating_sub(text_width) / 2,
+            TextAlign::Right => width.saturating_sub(text_width),
+        };
+        self.draw_text(y, x_region + offset, text.to_owned(), scale, c)
+    }
+
+    fn line_height(&self, size: usize) -> usize {
+        self.line_height_font(size, None)
+    }
+
+    fn line_height_font(&self, size: usize, font: Option<FontId>) -> usize {
+        let v_metrics = self.font(font).v_metrics(Scale::uniform(size as f32));
+        (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap).ceil() as usize
+    }
+
+    fn wrap_text(&self, text: &str, size: usize, max_width: usize) -> Vec<String> {
+        self.wrap_text_font(text, size, max_width, None)
+    }
+
+    fn wrap_text_font(
+        &self,
+        text: &str,
+        size: usize,
+        max_width: usize,
+        font: Option<FontId>,
+    ) -> Vec<String> {
+        let font = self.font(font);
+        let scale = Scale::uniform(size as f32);
+        let empty = Caret { x: 0.0, last: None };
+
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            let mut caret = empty;
+            for word in paragraph.split_whitespace() {
+                let candidate = match line.is_empty() {
+                    true => caret.advanced(font, scale, word),
+                    false => caret.advanced(font, scale, " ").advanced(font, scale, word),
+                };
+                if candidate.width() <= max_width {
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(word);
+                    caret = candidate;
+                    continue;
+                }
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                caret = empty.advanced(font, scale, word);
+                if caret.width() <= max_width {
+                    line.push_str(word);
+                    continue;
+                }
+
+                // The word doesn't fit on a line of its own, break it wherever the line is full
+                caret = empty;
+                for ch in word.chars() {
+                    let next = caret.advanced_char(font, scale, ch);
+                    match !line.is_empty() && next.width() > max_width {
+                        true => {
+                            lines.push(std::mem::replace(&mut line, ch.to_string()));
+                            caret = empty.advanced_char(font, scale, ch);
+                        }
+                        false => {
+                            line.push(ch);
+                            caret = next;
+                        }
+                    }
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn draw_rect(
+        &mut self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        border_px: usize,
+        c: color,
+    ) -> mxcfb_rect {
+        let border = min!(border_px, height, width);
+        self.fill_rect(y, x, border, width, c);
+        self.fill_rect(y + height - border, x, border, width, c);
+        self.fill_rect(y, x, height, border, c);
+        self.fill_rect(y, x + width - border, height, border, c);
+        return mxcfb_rect {
+            top: y as u32,
+            left: x as u32,
+            height: height as u32,
+            width: width as u32,
+        };
+    }
+
+    fn draw_group_box(
+        &mut self,
+        rect: mxcfb_rect,
+        title: &str,
+        scale: usize,
+        c: color,
+    ) -> mxcfb_rect {
+        const BORDER_PX: usize = 2;
+        let (top, left) = (rect.top as usize, rect.left as usize);
+        self.draw_rect(
+            top,
+            left,
+            rect.height as usize,
+            rect.width as usize,
+            BORDER_PX,
+            c,
+        );
+        if title.is_empty() {
+            return rect;
+        }
+
+        let (measured, ascent) = self.measure_text_origin(title, scale);
+        let text_height = measured.height as usize;
+
+        // The title is vertically centered on the top edge and starts one scale in
+        let padding = max!(scale / 4, 1);
+        let title_top = top.saturating_sub(text_height / 2);
+        let title_left = left + scale;
+        let gap = mxcfb_rect {
+            top: title_top as u32,
+            left: title_left.saturating_sub(padding) as u32,
+            height: max!(text_height, BORDER_PX) as u32,
+            width: (measured.width as usize + 2 * padding) as u32,
+        };
+
+        // Interrupt the border behind the title
+        self.fill_rect(
+            gap.top as usize,
+            gap.left as usize,
+            gap.height as usize,
+            gap.width as usize,
+            color::WHITE,
+        );
+        self.draw_text(title_top + ascent, title_left, title.to_owned(), scale, c);
+
+        let bottom = max!(rect.top + rect.height, gap.top + gap.height);
+        let (left, right) = (
+            min!(rect.left, gap.left),
+            max!(rect.left + rect.width, gap.left + gap.width),
+        );
+        return mxcfb_rect {
+            top: gap.top,
+            left,
+            height: bottom - gap.top,
+            width: right - left,
+        };
+    }
+
+    fn draw_stat_card(
+        &mut self,
+        rect: mxcfb_rect,
+        label: &str,
+        value: &str,
+        trend: &[f32],
+        c: color,
+    ) -> mxcfb_rect {
+        const BORDER_PX: usize = 2;
+        const MIN_SCALE: usize = 8;
+        let (top, left) = (rect.top as usize, rect.left as usize);
+        let (height, width) = (rect.height as usize, rect.width as usize);
+        self.draw_rect(top, left, height, width, BORDER_PX, c);
+
+        let padding = max!(height / 16, 4) + BORDER_PX;
+        let inner_width = width.saturating_sub(2 * padding);
+        let inner_height = height.saturating_sub(2 * padding);
+        let mut cursor_y = top + padding;
+        let text_left = left + padding;
+
+        // Returns the ascent, height and width of `text` at `scale`
+        let metrics = |fb: &Self, text: &str, scale: usize| {
+            let (measured, ascent) = fb.measure_text_origin(text, scale);
+            (ascent, measured.height as usize, measured.width as usize)
+        };
+
+        // The label takes a sixth of the card and the value two fifths, shrunk until it fits
+        let label_scale = max!(inner_height / 6, MIN_SCALE);
+        if !label.is_empty() {
+            let (ascent, text_height, _) = metrics(self, label, label_scale);
+            self.draw_text(cursor_y + ascent, text_left, label.to_owned(), label_scale, c);
+            cursor_y += text_height + padding / 2;
+        }
+        let mut value_scale = max!(inner_height * 2 / 5, MIN_SCALE);
+        while value_scale > MIN_SCALE && metrics(self, value, value_scale).2 > inner_width {
+            value_scale -= 1;
+        }
+        if !value.is_empty() {
+            let (ascent, text_height, _) = metrics(self, value, value_scale);
+            self.draw_text(cursor_y + ascent, text_left, value.to_owned(), value_scale, c);
+            cursor_y += text_height + padding / 2;
+        }
+
+        // The sparkline fills what is left, scaled to the range of the trend
+        let bottom = (top + height).saturating_sub(padding);
+        if trend.len() < 2 || bottom <= cursor_y + 2 || inner_width < 2 {
+            return rect;
+        }
+        let spark_height = (bottom - cursor_y - 1) as f32;
+        let lowest = trend.iter().cloned().fold(std::f32::INFINITY, f32::min);
+        let highest = trend.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max);
+        let point = |i: usize, v: f32| {
+            let x = text_left + i * (inner_width - 1) / (trend.len() - 1);
+            let t = match highest > lowest {
+                true => (v - lowest) / (highest - lowest),
+                false => 0.5,
+            };
+            ((bottom - 1) as f32 - t * spark_height, x as i32)
+        };
+        for (i, pair) in trend.windows(2).enumerate() {
+            let (y0, x0) = point(i, pair[0]);
+            let (y1, x1) = point(i + 1, pair[1]);
+            self.draw_line(y0.round() as i32, x0, y1.round() as i32, x1, 2, c);
+        }
+        rect
+    }
+
+    fn draw_scrollbar(
+        &mut self,
+        track: mxcfb_rect,
+        content_len: usize,
+        viewport_len: usize,
+        offset: usize,
+        c: color,
+    ) -> mxcfb_rect {
+        // Keeps the thumb large enough to be seen and touched on very long content
+        const MIN_THUMB_PX: usize = 16;
+
+        let vertical = track.height >= track.width;
+        let track_len = match vertical {
+            true => track.height as usize,
+            false => track.width as usize,
+        };
+
+        let (thumb_len, thumb_offset) = if content_len <= viewport_len || content_len == 0 {
+            (track_len, 0)
+        } else {
+            let thumb_len = min!(
+                max!(track_len * viewport_len / content_len, MIN_THUMB_PX),
+                track_len
+            );
+            let max_offset = content_len - viewport_len;
+            let offset = min!(offset, max_offset);
+            (thumb_len, (track_len - thumb_len) * offset / max_offset)
+        };
+
+        self.fill_rect(
+            track.top as usize,
+            track.left as usize,
+            track.height as usize,
+            track.width as usize,
+            color::WHITE,
+        );
+        let thumb = match vertical {
+            true => mxcfb_rect {
+                top: track.top + thumb_offset as u32,
+                left: track.left,
+                height: thumb_len as u32,
+                width: track.width,
+            },
+            false => mxcfb_rect {
+                top: track.top,
+                left: track.left + thumb_offset as u32,
+                height: track.height,
+                width: thumb_len as u32,
+            },
+        };
+        self.fill_rect(
+            thumb.top as usize,
+            thumb.left as usize,
+            thumb.height as usize,
+            thumb.width as usize,
+            c,
+        );
+        return thumb;
+    }
+
+    fn draw_badge(
+        &mut self,
+        center: cgmath::Point2<i32>,
+        count: u32,
+        fg: color,
+        bg: color,
+    ) -> mxcfb_rect {
+        const BADGE_TEXT_SCALE: usize = 32;
+        const BADGE_PADDING_PX: usize = 6;
+
+        let text = match count > 99 {
+            true => "99+".to_owned(),
+            false => count.to_string(),
+        };
+
+        let (text_rect, ascent) = self.measure_text_origin(&text, BADGE_TEXT_SCALE);
+        let height = text_rect.height as usize + 2 * BADGE_PADDING_PX;
+        // A single digit badge ends up as a circle, longer counts stretch it into a pill
+        let width = max!(height, text_rect.width as usize + height);
+
+        let top = max!(center.y - height as i32 / 2, 0) as usize;
+        let left = max!(center.x - width as i32 / 2, 0) as usize;
+        let pill = self.fill_rounded_rect(
+            top as u32,
+            left as u32,
+            height as u32,
+            width as u32,
+            height as u32 / 2,
+            bg,
+        );
+        self.draw_text(
+            top + BADGE_PADDING_PX + ascent,
+            left + (width - text_rect.width as usize) / 2,
+            text,
+            BADGE_TEXT_SCALE,
+            fg,
+        );
+        pill.intersection(&self.drawable_rect())
+            .unwrap_or_else(mxcfb_rect::invalid)
+    }
+
+    fn draw_clock(
+        &mut self,
+        center: cgmath::Point2<i32>,
+        radius: usize,
+        hour: u32,
+        minute: u32,
+        second: Option<u32>,
+        c: color,
+    ) -> mxcfb_rect {
+        let r = radius as f32;
+        // Angles are in radians, clockwise from 12 o'clock
+        let point_at = |angle: f32, dist: f32| {
+            (
+                center.y - (angle.cos() * dist).round() as i32,
+                center.x + (angle.sin() * dist).round() as i32,
+            )
+        };
+        let full_turn = 2.0 * std::f32::consts::PI;
+
+        self.draw_circle(center, radius as u32, c);
+        for tick in 0..12 {
+            let angle = tick as f32 * full_turn / 12.0;
+            // Quarter hours get longer and thicker ticks
+            let (inner, width) = match tick % 3 == 0 {
+                true => (0.8, 4),
+                false => (0.88, 2),
+            };
+            let (y0, x0) = point_at(angle, r * inner);
+            let (y1, x1) = point_at(angle, r * 0.95);
+            self.draw_line(y0, x0, y1, x1, width, c);
+        }
+
+        let minutes = minute % 60;
+        let seconds = second.map(|s| s % 60);
+        // Each hand as the fraction of a turn it points at, its length and its width
+        let mut hands = vec![
+            (((hour % 12) as f32 + minutes as f32 / 60.0) / 12.0, 0.5, 6),
+            ((minutes as f32 + seconds.unwrap_or(0) as f32 / 60.0) / 60.0, 0.75, 4),
+        ];
+        if let Some(seconds) = seconds {
+            hands.push((seconds as f32 / 60.0, 0.9, 1));
+        }
+
+        let mut covered: Option<mxcfb_rect> = None;
+        for (turns, length, width) in hands {
+            let (y1, x1) = point_at(turns * full_turn, r * length);
+            let hand = self.draw_line(center.y, center.x, y1, x1, width, c);
+            covered = Some(match covered {
+                Some(r) => r.union(&hand),
+                None => hand,
+            });
+        }
+        covered.unwrap_or_else(mxcfb_rect::invalid)
+    }
+
+    fn fill_rect_gradient(
+        &mut self,
+        rect: mxcfb_rect,
+        from: color,
+        to: color,
+        direction: GradientDirection,
+        dither_strength: f32,
+        gamma: Option<f32>,
+    ) -> mxcfb_rect {
+        let (from, to) = (from.as_native()[0] as f32, to.as_native()[0] as f32);
+        let strength = dither_strength.max(0.0).min(1.0);
+        let inv_gamma = match gamma {
+            Some(g) if g > 0.0 => 1.0 / g,
+            _ => 1.0,
+        };
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        let steps = match direction {
+            GradientDirection::Horizontal => width,
+            GradientDirection::Vertical => height,
+        };
+
+        let mut luma = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let step = match direction {
+                    GradientDirection::Horizontal => x,
+                    GradientDirection::Vertical => y,
+                };
+                let t = match steps > 1 {
+                    true => step as f32 / (steps - 1) as f32,
+                    false => 0.0,
+                };
+                let v = ((from + (to - from) * t) / 255.0).powf(inv_gamma);
+                // Offsets each pixel by up to half a level before quantizing to what the panel
+                // shows, which trades the bands for a pattern as the strength goes up
+                let threshold = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+                let level = (v * (GRADIENT_LEVELS - 1.0) + threshold * strength).round();
+                let level = level.max(0.0).min(GRADIENT_LEVELS - 1.0);
+                luma.push((level * 255.0 / (GRADIENT_LEVELS - 1.0)) as u8);
+            }
+        }
+        self.write_luma(rect, &luma)
+    }
+
     fn fill_rect(&mut self, y: usize, x: usize, height: usize, width: usize, c: color) {
-        for ypos in y..y + height {
-            for xpos in x..x + width {
-                self.write_pixel(ypos, xpos, c);
+        self.fill_rect_checked(y, x, height, width, c);
+    }
+
+    fn fill_rect_checked(
+        &mut self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        c: color,
+    ) -> Option<mxcfb_rect> {
+        #[cfg(feature = "draw-stats")]
+        let started = Instant::now();
+        let (yres, xres) = (
+            self.var_screen_info.yres as usize,
+            self.var_screen_info.xres as usize,
+        );
+        if y >= yres || x >= xres {
+            return None;
+        }
+        // Clamped to the screen before the conversion so that huge sizes can't wrap around
+        let rect = mxcfb_rect {
+            top: y as u32,
+            left: x as u32,
+            height: min!(height, yres - y) as u32,
+            width: min!(width, xres - x) as u32,
+        };
+        let clipped = match rect.intersection(&self.drawable_rect()) {
+            Some(r) => r,
+            None => return None,
+        };
+        for ypos in clipped.top..clipped.top + clipped.height {
+            for xpos in clipped.left..clipped.left + clipped.width {
+                self.write_pixel(ypos as usize, xpos as usize, c);
             }
         }
+        #[cfg(feature = "draw-stats")]
+        self.draw_stats.fill_rect.record(started, &clipped);
+        Some(clipped)
+    }
+
+    fn fill_rounded_rect(
+        &mut self,
+        top: u32,
+        left: u32,
+        height: u32,
+        width: u32,
+        radius: u32,
+        v: color,
+    ) -> mxcfb_rect {
+        let r = min!(radius, height / 2, width / 2) as i64;
+        let (top, left, height, width) = (top as i64, left as i64, height as i64, width as i64);
+        // Centers of the corner arcs, overlapping when the radius is half of an even side
+        let (upper, lower) = (top + r, top + height - 1 - r);
+        for y in top..top + height {
+            // Same spans as `fill_circle` over the rows of the corners
+            let dy = max!(upper - y, y - lower, 0);
+            let half = ((r * r - dy * dy) as f32).sqrt() as i64;
+            let inset = max!(r - half, 0);
+            self.fill_rect(
+                y as usize,
+                (left + inset) as usize,
+                1,
+                (width - 2 * inset) as usize,
+                v,
+            );
+        }
+        mxcfb_rect {
+            top: top as u32,
+            left: left as u32,
+            height: height as u32,
+            width: width as u32,
+        }
+    }
+
+    fn invert_rect(&mut self, region: &mxcfb_rect) -> mxcfb_rect {
+        let clipped = match region.intersection(&self.drawable_rect()) {
+            Some(r) => r,
+            None => return mxcfb_rect::invalid(),
+        };
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        let row_len = clipped.width as usize * bytespp;
+        let begin = self.frame_ptr_mut();
+        for y in clipped.top as usize..(clipped.top + clipped.height) as usize {
+            let start = y * line_length + clipped.left as usize * bytespp;
+            let row = unsafe {
+                ::std::slice::from_raw_parts_mut(begin.offset(start as isize), row_len)
+            };
+            // Complementing every byte complements each RGB565 value as well as gray levels
+            for byte in row.iter_mut() {
+                *byte = !*byte;
+            }
+        }
+        clipped
     }
 
     fn clear(&mut self) {
+        if let Some(viewport) = self.viewport {
+            return self.fill_rect(
+                viewport.top as usize,
+                viewport.left as usize,
+                viewport.height as usize,
+                viewport.width as usize,
+                color::WHITE,
+            );
+        }
         let h = self.var_screen_info.yres as usize;
         let line_length = self.fix_screen_info.line_length as usize;
         unsafe {
             libc::memset(
-                self.frame.data() as *mut libc::c_void,
+                self.frame_ptr_mut() as *mut libc::c_void,
                 std::i32::MAX,
                 line_length * h,
             );
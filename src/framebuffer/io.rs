@@ -1,27 +1,100 @@
 #![allow(dead_code)]
+use image;
+use libc;
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use error::Error;
 use framebuffer;
 
+/// Writes all of `data` to `fd`, retrying on partial writes and interruptions
+fn write_all_fd(fd: RawFd, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let written =
+            unsafe { libc::write(fd, data.as_ptr() as *const libc::c_void, data.len()) };
+        if written < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if written == 0 {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "fd accepted no more data"));
+        }
+        data = &data[written as usize..];
+    }
+    Ok(())
+}
+
 impl<'a> framebuffer::FramebufferIO for framebuffer::core::Framebuffer<'a> {
     fn write_frame(&mut self, frame: &[u8]) {
         unsafe {
-            let begin = self.frame.data() as *mut u8;
+            let begin = self.frame_ptr_mut();
             for (i, elem) in frame.iter().enumerate() {
                 *(begin.offset(i as isize)) = *elem;
             }
         }
     }
 
+    fn write_luma(
+        &mut self,
+        rect: framebuffer::common::mxcfb_rect,
+        luma: &[u8],
+    ) -> framebuffer::common::mxcfb_rect {
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        if luma.len() != width * height {
+            error!(
+                "write_luma(..) expected {0} bytes for a {1}x{2} rect but got {3}",
+                width * height,
+                width,
+                height,
+                luma.len()
+            );
+            return framebuffer::common::mxcfb_rect::invalid();
+        }
+
+        let visible = match rect.intersection(&self.drawable_rect()) {
+            Some(r) => r,
+            None => return framebuffer::common::mxcfb_rect::invalid(),
+        };
+
+        // Clip once up front so that the inner loop can write straight to the mapped memory
+        let skip_rows = (visible.top - rect.top) as usize;
+        let skip_cols = (visible.left - rect.left) as usize;
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+
+        let begin = self.frame_ptr_mut();
+        for row in 0..visible.height as usize {
+            let start = (skip_rows + row) * width + skip_cols;
+            let src = &luma[start..start + visible.width as usize];
+            let (y, x) = (visible.top as usize + row, visible.left as usize);
+            let mut curr_index = (y * line_length + x * bytespp) as isize;
+            for &level in src {
+                let components = framebuffer::common::color::GRAY(level).as_native();
+                unsafe {
+                    *(begin.offset(curr_index)) = components[0];
+                    *(begin.offset(curr_index + 1)) = components[1];
+                    *(begin.offset(curr_index + 2)) = components[2];
+                    *(begin.offset(curr_index + 3)) = components[3];
+                }
+                curr_index += bytespp as isize;
+            }
+        }
+        visible
+    }
+
     fn write_pixel(&mut self, y: usize, x: usize, v: framebuffer::common::color) {
-        let w = self.var_screen_info.xres as usize;
-        let h = self.var_screen_info.yres as usize;
-        if y >= h || x >= w {
+        if !self.is_drawable(y, x) {
             return;
         }
         let line_length = self.fix_screen_info.line_length as usize;
         let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
         let curr_index = (y * line_length + x * bytespp) as isize;
 
-        let begin = self.frame.data() as *mut u8;
+        let begin = self.frame_ptr_mut();
         let components = v.as_native();
         unsafe {
             *(begin.offset(curr_index)) = components[0];
@@ -31,7 +104,7 @@ impl<'a> framebuffer::FramebufferIO for framebuffer::core::Framebuffer<'a> {
         }
     }
 
-    fn read_pixel(&mut self, y: usize, x: usize) -> framebuffer::common::color {
+    fn read_pixel(&self, y: usize, x: usize) -> framebuffer::common::color {
         let w = self.var_screen_info.xres as usize;
         let h = self.var_screen_info.yres as usize;
         if y >= h || x >= w {
@@ -50,10 +123,194 @@ impl<'a> framebuffer::FramebufferIO for framebuffer::core::Framebuffer<'a> {
         )
     }
 
-    fn read_offset(&mut self, ofst: isize) -> u8 {
+    fn read_offset(&self, ofst: isize) -> u8 {
         unsafe {
-            let begin = self.frame.data() as *mut u8;
+            let begin = self.frame_ptr();
             return *(begin.offset(ofst));
         }
     }
+
+    fn dump_region(
+        &self,
+        rect: framebuffer::common::mxcfb_rect,
+    ) -> Result<Vec<u8>, Error> {
+        if rect.width == 0 || rect.height == 0 {
+            return Err(Error::InvalidArgument("Unable to dump a region with no area"));
+        }
+        if rect.left + rect.width > self.var_screen_info.xres
+            || rect.top + rect.height > self.var_screen_info.yres
+        {
+            return Err(Error::OutOfBounds);
+        }
+
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        let row_len = rect.width as usize * bytespp;
+
+        let begin = self.frame_ptr();
+        let mut dump = Vec::with_capacity(row_len * rect.height as usize);
+        for row in 0..rect.height as usize {
+            let offset = (rect.top as usize + row) * line_length + rect.left as usize * bytespp;
+            let line = unsafe {
+                ::std::slice::from_raw_parts(begin.offset(offset as isize), row_len)
+            };
+            dump.extend_from_slice(line);
+        }
+        Ok(dump)
+    }
+
+    fn restore_region(
+        &mut self,
+        rect: framebuffer::common::mxcfb_rect,
+        data: &[u8],
+    ) -> Result<u32, Error> {
+        if rect.width == 0 || rect.height == 0 {
+            return Err(Error::InvalidArgument("Unable to restore a region with no area"));
+        }
+        if rect.left + rect.width > self.var_screen_info.xres
+            || rect.top + rect.height > self.var_screen_info.yres
+        {
+            return Err(Error::OutOfBounds);
+        }
+
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        let row_len = rect.width as usize * bytespp;
+        if data.len() != row_len * rect.height as usize {
+            return Err(Error::InvalidArgument(
+                "Cannot restore region due to mismatched size",
+            ));
+        }
+
+        let begin = self.frame_ptr_mut();
+        for (row, line) in data.chunks(row_len).enumerate() {
+            let offset = (rect.top as usize + row) * line_length + rect.left as usize * bytespp;
+            unsafe {
+                ::std::ptr::copy_nonoverlapping(
+                    line.as_ptr(),
+                    begin.offset(offset as isize),
+                    row_len,
+                );
+            }
+        }
+        Ok(data.len() as u32)
+    }
+
+    fn copy_rect(
+        &mut self,
+        src: framebuffer::common::mxcfb_rect,
+        dest_top: u32,
+        dest_left: u32,
+    ) -> framebuffer::common::mxcfb_rect {
+        let (xres, yres) = (self.var_screen_info.xres, self.var_screen_info.yres);
+        if src.left >= xres || src.top >= yres {
+            return framebuffer::common::mxcfb_rect::invalid();
+        }
+        let bounds = self.drawable_rect();
+        let (right, bottom) = (bounds.left + bounds.width, bounds.top + bounds.height);
+        if dest_left < bounds.left
+            || dest_top < bounds.top
+            || dest_left >= right
+            || dest_top >= bottom
+        {
+            return framebuffer::common::mxcfb_rect::invalid();
+        }
+
+        // Only copy what is on screen at the source and within the viewport at the destination
+        let width = *[src.width, xres - src.left, right - dest_left]
+            .iter()
+            .min()
+            .unwrap() as usize;
+        let height = *[src.height, yres - src.top, bottom - dest_top]
+            .iter()
+            .min()
+            .unwrap() as usize;
+
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        let begin = self.frame_ptr_mut();
+        let copy_row = |row: usize| {
+            let from = (src.top as usize + row) * line_length + src.left as usize * bytespp;
+            let to = (dest_top as usize + row) * line_length + dest_left as usize * bytespp;
+            unsafe {
+                ::std::ptr::copy(
+                    begin.offset(from as isize),
+                    begin.offset(to as isize),
+                    width * bytespp,
+                );
+            }
+        };
+
+        // When moving down, start from the bottom row so that rows aren't overwritten
+        // before they are copied
+        match dest_top > src.top {
+            true => (0..height).rev().for_each(copy_row),
+            false => (0..height).for_each(copy_row),
+        }
+
+        framebuffer::common::mxcfb_rect {
+            top: dest_top,
+            left: dest_left,
+            width: width as u32,
+            height: height as u32,
+        }
+    }
+
+    fn blit_to_fd(
+        &self,
+        rect: framebuffer::common::mxcfb_rect,
+        target: RawFd,
+        target_stride: usize,
+    ) -> Result<usize, Error> {
+        if rect.left + rect.width > self.var_screen_info.xres
+            || rect.top + rect.height > self.var_screen_info.yres
+        {
+            return Err(Error::OutOfBounds);
+        }
+        let line_length = self.fix_screen_info.line_length as usize;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        let row_len = rect.width as usize * bytespp;
+        if target_stride < row_len {
+            return Err(Error::InvalidArgument(
+                "target_stride is shorter than a row of the region",
+            ));
+        }
+
+        let padding = vec![0u8; target_stride - row_len];
+        let begin = self.frame_ptr();
+        for row in 0..rect.height as usize {
+            let offset = (rect.top as usize + row) * line_length + rect.left as usize * bytespp;
+            let line = unsafe {
+                ::std::slice::from_raw_parts(begin.offset(offset as isize), row_len)
+            };
+            write_all_fd(target, line)?;
+            write_all_fd(target, &padding)?;
+        }
+        Ok(target_stride * rect.height as usize)
+    }
+
+    fn region_to_png(
+        &self,
+        rect: framebuffer::common::mxcfb_rect,
+        color_type: image::ColorType,
+    ) -> Result<Vec<u8>, Error> {
+        let dump = self.dump_region(rect)?;
+        let bytespp = (self.var_screen_info.bits_per_pixel / 8) as usize;
+        // The first component of a pixel carries its gray level
+        let levels = dump.chunks(bytespp).map(|pixel| pixel[0]);
+        let data: Vec<u8> = match color_type {
+            image::ColorType::Gray(8) => levels.collect(),
+            image::ColorType::RGB(8) => levels.flat_map(|l| vec![l, l, l]).collect(),
+            other => {
+                return Err(Error::UnsupportedFormat(format!(
+                    "PNG color type {0:?}, only Gray(8) and RGB(8) are supported",
+                    other
+                )))
+            }
+        };
+
+        let mut png = Vec::new();
+        image::png::PNGEncoder::new(&mut png).encode(&data, rect.width, rect.height, color_type)?;
+        Ok(png)
+    }
 }
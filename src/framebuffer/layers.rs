@@ -0,0 +1,238 @@
+use image;
+use image::GenericImage;
+
+use std::io;
+
+use framebuffer::common;
+use framebuffer::core;
+use framebuffer::refresh::PartialRefreshMode;
+use framebuffer::{FramebufferIO, FramebufferRefresh};
+
+/// Blends `src` over `dst` with `alpha` ranging from 0 (transparent) to 255 (opaque)
+pub fn blend(dst: u8, src: u8, alpha: u8) -> u8 {
+    let (dst, src, alpha) = (dst as u32, src as u32, alpha as u32);
+    ((src * alpha + dst * (255 - alpha) + 127) / 255) as u8
+}
+
+/// Reduces a `color` to the gray level it is displayed with
+fn luma_of(c: common::color) -> u8 {
+    c.as_native()[0]
+}
+
+/// An offscreen 8-bit grayscale buffer with per pixel coverage. A new layer is fully
+/// transparent.
+pub struct Layer {
+    pub visible: bool,
+    width: u32,
+    height: u32,
+    luma: Vec<u8>,
+    alpha: Vec<u8>,
+}
+
+impl Layer {
+    pub fn new(height: u32, width: u32) -> Layer {
+        let len = (width * height) as usize;
+        Layer {
+            visible: true,
+            width,
+            height,
+            luma: vec![0xFF; len],
+            alpha: vec![0; len],
+        }
+    }
+
+    /// Clips `rect` to the layer, returning `None` if nothing is left
+    fn clip(&self, rect: &common::mxcfb_rect) -> Option<common::mxcfb_rect> {
+        if rect.left >= self.width || rect.top >= self.height {
+            return None;
+        }
+        let width = ::std::cmp::min(rect.width, self.width - rect.left);
+        let height = ::std::cmp::min(rect.height, self.height - rect.top);
+        match width == 0 || height == 0 {
+            true => None,
+            false => Some(common::mxcfb_rect {
+                top: rect.top,
+                left: rect.left,
+                width,
+                height,
+            }),
+        }
+    }
+
+    /// Sets the pixel at `(y, x)` to `luma` with coverage `alpha`, replacing what was there
+    pub fn set_pixel(&mut self, y: u32, x: u32, luma: u8, alpha: u8) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        let i = (y * self.width + x) as usize;
+        self.luma[i] = luma;
+        self.alpha[i] = alpha;
+    }
+
+    /// Returns the gray level and coverage of the pixel at `(y, x)`
+    pub fn pixel(&self, y: u32, x: u32) -> Option<(u8, u8)> {
+        if y >= self.height || x >= self.width {
+            return None;
+        }
+        let i = (y * self.width + x) as usize;
+        Some((self.luma[i], self.alpha[i]))
+    }
+
+    /// Fills `rect` with an opaque `c`. Returns the affected rect.
+    pub fn fill_rect(&mut self, rect: common::mxcfb_rect, c: common::color) -> common::mxcfb_rect {
+        self.fill_rect_alpha(rect, luma_of(c), 0xFF)
+    }
+
+    /// Fills `rect` with `luma` at coverage `alpha`. Returns the affected rect.
+    pub fn fill_rect_alpha(
+        &mut self,
+        rect: common::mxcfb_rect,
+        luma: u8,
+        alpha: u8,
+    ) -> common::mxcfb_rect {
+        let rect = match self.clip(&rect) {
+            Some(r) => r,
+            None => return common::mxcfb_rect::invalid(),
+        };
+        for y in rect.top..rect.top + rect.height {
+            let start = (y * self.width + rect.left) as usize;
+            let end = start + rect.width as usize;
+            for v in &mut self.luma[start..end] {
+                *v = luma;
+            }
+            for a in &mut self.alpha[start..end] {
+                *a = alpha;
+            }
+        }
+        rect
+    }
+
+    /// Makes `rect` fully transparent again. Returns the affected rect.
+    pub fn clear_rect(&mut self, rect: common::mxcfb_rect) -> common::mxcfb_rect {
+        self.fill_rect_alpha(rect, 0xFF, 0)
+    }
+
+    /// Makes the whole layer transparent
+    pub fn clear(&mut self) {
+        for a in self.alpha.iter_mut() {
+            *a = 0;
+        }
+    }
+
+    /// Draws `img` opaque at y=top, x=left coordinates with 1:1 scaling. Returns the
+    /// affected rect.
+    pub fn draw_image(
+        &mut self,
+        img: &image::DynamicImage,
+        top: u32,
+        left: u32,
+    ) -> common::mxcfb_rect {
+        let luma = img.to_luma();
+        let rect = match self.clip(&common::mxcfb_rect {
+            top,
+            left,
+            width: img.width(),
+            height: img.height(),
+        }) {
+            Some(r) => r,
+            None => return common::mxcfb_rect::invalid(),
+        };
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let level = luma.get_pixel(x, y).data[0];
+                self.set_pixel(top + y, left + x, level, 0xFF);
+            }
+        }
+        rect
+    }
+}
+
+/// An ordered set of layers of the size of the screen that are composited bottom to top
+/// over a white canvas. Drawing into one layer leaves the others untouched, so that for
+/// instance an overlay can be updated without redrawing the content under it.
+pub struct LayerStack {
+    width: u32,
+    height: u32,
+    layers: Vec<Layer>,
+}
+
+impl LayerStack {
+    /// Creates a stack of `count` transparent layers of `height` by `width` pixels
+    pub fn new(height: u32, width: u32, count: usize) -> LayerStack {
+        LayerStack {
+            width,
+            height,
+            layers: (0..count).map(|_| Layer::new(height, width)).collect(),
+        }
+    }
+
+    /// Adds a transparent layer on top of the others and returns its index
+    pub fn push_layer(&mut self) -> usize {
+        self.layers.push(Layer::new(self.height, self.width));
+        self.layers.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn layer(&self, index: usize) -> Option<&Layer> {
+        self.layers.get(index)
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut Layer> {
+        self.layers.get_mut(index)
+    }
+
+    /// Composites the visible layers within `dirty` into row-major gray levels
+    fn composite(&self, dirty: &common::mxcfb_rect) -> Vec<u8> {
+        let mut out = vec![0xFF; (dirty.width * dirty.height) as usize];
+        for layer in self.layers.iter().filter(|l| l.visible) {
+            for row in 0..dirty.height {
+                let start = ((dirty.top + row) * self.width + dirty.left) as usize;
+                let row_start = (row * dirty.width) as usize;
+                let dst = &mut out[row_start..row_start + dirty.width as usize];
+                let luma = &layer.luma[start..start + dirty.width as usize];
+                let alpha = &layer.alpha[start..start + dirty.width as usize];
+                for ((d, &l), &a) in dst.iter_mut().zip(luma).zip(alpha) {
+                    *d = match a {
+                        0 => *d,
+                        0xFF => l,
+                        _ => blend(*d, l, a),
+                    };
+                }
+            }
+        }
+        out
+    }
+
+    /// Writes the composited `dirty` region into the framebuffer and refreshes only that
+    /// region asynchronously. Returns the marker, or 0 if `dirty` is off screen.
+    pub fn composite_and_present(
+        &self,
+        fb: &mut core::Framebuffer,
+        dirty: common::mxcfb_rect,
+    ) -> io::Result<u32> {
+        let dirty = match self.layers.first() {
+            Some(l) => match l.clip(&dirty) {
+                Some(r) => r,
+                None => return Ok(0),
+            },
+            None => return Ok(0),
+        };
+        let luma = self.composite(&dirty);
+        let written = fb.write_luma(dirty, &luma);
+        fb.partial_refresh(
+            &written,
+            PartialRefreshMode::Async,
+            common::waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        )
+    }
+}
@@ -1,20 +1,69 @@
 pub mod common;
 pub mod mxcfb;
 pub mod screeninfo;
+pub mod layers;
+pub mod offscreen;
+pub mod stroke;
+pub mod shared;
+
+use error;
+use image;
 
 pub mod io;
 pub trait FramebufferIO {
     /// Writes an arbitrary length frame into the framebuffer
     fn write_frame(&mut self, frame: &[u8]);
+    /// Writes row-major 8-bit grayscale `luma` data into `rect`, converting it to the
+    /// panel format. `luma.len()` must equal `rect.width * rect.height`, otherwise
+    /// nothing is written and `mxcfb_rect::invalid()` is returned. Returns the rect to refresh.
+    fn write_luma(&mut self, rect: common::mxcfb_rect, luma: &[u8]) -> common::mxcfb_rect;
     /// Writes a single pixel at `(y, x)` with value `v`
     fn write_pixel(&mut self, y: usize, x: usize, v: common::color);
-    /// Reads the value of the pixel at `(y, x)`
-    fn read_pixel(&mut self, y: usize, x: usize) -> common::color;
+    /// Reads the value of the pixel at `(y, x)` from the mapped framebuffer memory, which
+    /// makes it possible to test against what is already drawn. Coordinates off screen read
+    /// as `color::WHITE`.
+    fn read_pixel(&self, y: usize, x: usize) -> common::color;
     /// Reads the value at offset `ofst` from the mmapp'ed framebuffer region
-    fn read_offset(&mut self, ofst: isize) -> u8;
+    fn read_offset(&self, ofst: isize) -> u8;
+    /// Dumps the contents of `rect` row by row in the native pixel format so that it can
+    /// later be put back with `restore_region`
+    fn dump_region(&self, rect: common::mxcfb_rect) -> Result<Vec<u8>, error::Error>;
+    /// Writes `data` previously obtained from `dump_region` with the same `rect` back into
+    /// the framebuffer. Returns the number of bytes written.
+    fn restore_region(
+        &mut self,
+        rect: common::mxcfb_rect,
+        data: &[u8],
+    ) -> Result<u32, error::Error>;
+    /// Writes the pixels of `rect` in the native pixel format to the file descriptor `target`,
+    /// e.g. a pipe to a virtual display, row by row. Each row is padded with zeroes up to
+    /// `target_stride` bytes. Returns the number of bytes written.
+    fn blit_to_fd(
+        &self,
+        rect: common::mxcfb_rect,
+        target: std::os::unix::io::RawFd,
+        target_stride: usize,
+    ) -> Result<usize, error::Error>;
+    /// Encodes the contents of `rect` as a PNG in memory. `color_type` can be
+    /// `image::ColorType::Gray(8)`, or `image::ColorType::RGB(8)` for consumers that don't
+    /// handle grayscale PNGs at the cost of a larger payload.
+    fn region_to_png(
+        &self,
+        rect: common::mxcfb_rect,
+        color_type: image::ColorType,
+    ) -> Result<Vec<u8>, error::Error>;
+    /// Copies the pixels of `src` so that its top left corner ends up at
+    /// `(dest_top, dest_left)`. The regions may overlap, which makes this suitable for
+    /// scrolling content. Returns the destination rect, clipped to the screen.
+    fn copy_rect(
+        &mut self,
+        src: common::mxcfb_rect,
+        dest_top: u32,
+        dest_left: u32,
+    ) -> common::mxcfb_rect;
 }
 
-use image;
+use cgmath;
 pub mod draw;
 pub trait FramebufferDraw {
     /// Draws `img` at y=top, x=left coordinates with 1:1 scaling
@@ -24,6 +73,33 @@ pub trait FramebufferDraw {
         top: usize,
         left: usize,
     ) -> common::mxcfb_rect;
+    /// Same as `draw_grayscale_image` but composites `img` over what is already on the
+    /// framebuffer by its alpha channel. Fully transparent pixels are left untouched.
+    fn draw_image_alpha(
+        &mut self,
+        img: &image::DynamicImage,
+        top: usize,
+        left: usize,
+    ) -> common::mxcfb_rect;
+    /// Draws `img` at y=top, x=left coordinates with every pixel thresholded to black or
+    /// white, which keeps re-displayed screenshots of text sharp and is faster than
+    /// `draw_grayscale_image`. See `draw::is_bilevel_image` to tell when it is suitable.
+    fn draw_bilevel_image(
+        &mut self,
+        img: &image::DynamicImage,
+        top: usize,
+        left: usize,
+    ) -> common::mxcfb_rect;
+    /// Draws `img` at y=top, x=left coordinates with 1:1 scaling, reduced to the gray levels
+    /// of the panel with `algorithm`. Only the part of `img` within the drawable area is
+    /// processed. Returns the rect that was written.
+    fn draw_dithered_image(
+        &mut self,
+        img: &image::DynamicImage,
+        top: usize,
+        left: usize,
+        algorithm: draw::DitherAlgorithm,
+    ) -> common::mxcfb_rect;
     /// Draws a straight line
     fn draw_line(
         &mut self,
@@ -34,20 +110,73 @@ pub trait FramebufferDraw {
         width: usize,
         v: common::color,
     ) -> common::mxcfb_rect;
-    /// Draws a circle using Bresenham circle algorithm
+    /// Draws a straight line with `ink`, which can either be a color,
+    /// `EraseOrColor::Erase` to put back the background saved with `capture_background`
+    /// along the stroke or `EraseOrColor::Accumulate` to darken what is already there
+    fn draw_line_ink(
+        &mut self,
+        y0: i32,
+        x0: i32,
+        y1: i32,
+        x1: i32,
+        width: usize,
+        ink: draw::EraseOrColor,
+    ) -> common::mxcfb_rect;
+    /// Saves the current contents of the framebuffer as the background that erasing
+    /// strokes restore. Without a captured background, erasing paints white.
+    fn capture_background(&mut self);
+    /// Drops the background saved by `capture_background`
+    fn release_background(&mut self);
+    /// Draws the outline of the circle of `radius` around `center` using the midpoint circle
+    /// algorithm. The parts of the circle off screen are skipped, even if `center` is.
+    /// Returns the bounding rect of the circle clipped to the screen.
     fn draw_circle(
         &mut self,
-        y: usize,
-        x: usize,
-        rad: usize,
+        center: cgmath::Point2<i32>,
+        radius: u32,
         c: common::color,
     ) -> common::mxcfb_rect;
-    /// Fills a circle
+    /// Same as `draw_circle` but fills the circle, one horizontal span per row
     fn fill_circle(
         &mut self,
-        y: usize,
-        x: usize,
-        rad: usize,
+        center: cgmath::Point2<i32>,
+        radius: u32,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Writes `c` at each of `points` straight to the mapped memory, silently skipping the
+    /// points outside the drawable area. The single pixel counterpart is
+    /// `FramebufferIO::write_pixel`. Returns the bounding rect of the points drawn, so
+    /// that one `partial_refresh` covers all of them.
+    fn draw_points(
+        &mut self,
+        points: &[cgmath::Point2<i32>],
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Connects consecutive `points` with `draw_line`s of `width`. Returns the bounding rect
+    /// of the lines clipped to the drawable area.
+    fn draw_polyline(
+        &mut self,
+        points: &[cgmath::Point2<i32>],
+        width: usize,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Fills the polygon outlined by `points`, its last point connecting back to the first,
+    /// with the even-odd rule so that self-intersecting outlines leave holes. Returns the
+    /// bounding rect of the polygon clipped to the drawable area, or `mxcfb_rect::invalid()`
+    /// without drawing anything when given less than three points.
+    fn fill_polygon(
+        &mut self,
+        points: &[cgmath::Point2<i32>],
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws a freehand stroke through `segments`, pairs of a point and a pen pressure
+    /// normalized to `0.0..1.0`. The width of the stroke is `base_width` scaled by the
+    /// pressure and interpolated between consecutive samples, and the area it sweeps is
+    /// filled. Returns the bounding rect of the stroke clipped to the drawable area.
+    fn draw_dynamic_stroke(
+        &mut self,
+        segments: &[(cgmath::Point2<f32>, f32)],
+        base_width: f32,
         c: common::color,
     ) -> common::mxcfb_rect;
     /// Draws a bezier curve begining at `startpt`, with control point `ctrlpt`, ending at `endpt` with `color`
@@ -58,7 +187,8 @@ pub trait FramebufferDraw {
         endpt: (f32, f32),
         c: common::color,
     ) -> common::mxcfb_rect;
-    /// Draws `text` at `(y, x)` with `color` using `scale`
+    /// Draws `text` at `(y, x)` with `color` using `scale`, blending the glyphs with what is
+    /// already on the framebuffer
     fn draw_text(
         &mut self,
         y: usize,
@@ -67,8 +197,192 @@ pub trait FramebufferDraw {
         size: usize,
         col: common::color,
     ) -> common::mxcfb_rect;
-    /// Fills rectangle of `height` and `width` at `(y, x)`
+    /// Same as `draw_text` but with the font loaded as `font` with `Framebuffer::load_font`,
+    /// or the default font when `None`
+    fn draw_text_font(
+        &mut self,
+        y: usize,
+        x: usize,
+        text: &str,
+        size: usize,
+        col: common::color,
+        font: Option<draw::FontId>,
+    ) -> common::mxcfb_rect;
+    /// Same as `draw_text` but rounds the blended edges of the glyphs to the 16 gray levels
+    /// of the panel, which keeps large text smooth and free of dithering noise once
+    /// refreshed with `WAVEFORM_MODE_GC16`
+    fn draw_text_aa(
+        &mut self,
+        y: usize,
+        x: usize,
+        c: common::color,
+        scale: usize,
+        text: &str,
+    ) -> common::mxcfb_rect;
+    /// Returns the rect `draw_text` would cover if called with the same arguments,
+    /// without touching the framebuffer
+    fn measure_text(&self, y: usize, x: usize, text: &str, size: usize) -> common::mxcfb_rect;
+    /// Measures `text` at `scale` for laying it out from its top left corner. Returns its
+    /// rect as `measure_text` does for the baseline at `(scale, 0)`, along with its ascent:
+    /// the distance from the top of the glyphs to the baseline.
+    fn measure_text_origin(&self, text: &str, scale: usize) -> (common::mxcfb_rect, usize);
+    /// Returns the rect `draw_text_font` would cover if called with the same arguments
+    fn measure_text_font(
+        &self,
+        y: usize,
+        x: usize,
+        text: &str,
+        size: usize,
+        font: Option<draw::FontId>,
+    ) -> common::mxcfb_rect;
+    /// Returns one rect per character of `text` as laid out by `draw_text` with the same
+    /// arguments, spanning the advance of the character horizontally and the ascent to the
+    /// descent of the font vertically. Unlike the rect returned by `draw_text`, the rects of
+    /// adjacent characters touch and whitespace has a rect as well.
+    fn measure_glyphs(
+        &self,
+        y: usize,
+        x: usize,
+        text: &str,
+        size: usize,
+    ) -> Vec<common::mxcfb_rect>;
+    /// Draws `text` with its baseline at `y`, aligned within the `width` pixels starting at
+    /// `x_region` according to `align`. The text is measured by the advance of its glyphs as
+    /// laid out by `draw_text`, so kerning is the same. Returns the drawn rect.
+    fn draw_text_aligned(
+        &mut self,
+        y: usize,
+        x_region: usize,
+        width: usize,
+        align: draw::TextAlign,
+        c: common::color,
+        scale: usize,
+        text: &str,
+    ) -> common::mxcfb_rect;
+    /// Returns the distance between the baselines of consecutive lines of text of `size`
+    fn line_height(&self, size: usize) -> usize;
+    /// Same as `line_height` for `font`, see `draw_text_font`
+    fn line_height_font(&self, size: usize, font: Option<draw::FontId>) -> usize;
+    /// Breaks `text` into lines no wider than `max_width` when drawn at `size`. Lines are
+    /// broken on whitespace and at every newline, and words longer than a line are broken
+    /// wherever the line is full.
+    fn wrap_text(&self, text: &str, size: usize, max_width: usize) -> Vec<String>;
+    /// Same as `wrap_text` but measures the lines as drawn with `font`, see `draw_text_font`
+    fn wrap_text_font(
+        &self,
+        text: &str,
+        size: usize,
+        max_width: usize,
+        font: Option<draw::FontId>,
+    ) -> Vec<String>;
+    /// Draws the outline of the rectangle of `height` and `width` at `(y, x)` with
+    /// `border_px` thick edges drawn inwards
+    fn draw_rect(
+        &mut self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        border_px: usize,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws the outline of `rect` with `title` rendered at `scale` on top of its upper edge,
+    /// interrupting the border like a classic group box. Returns the area covered by the box
+    /// and its title.
+    fn draw_group_box(
+        &mut self,
+        rect: common::mxcfb_rect,
+        title: &str,
+        scale: usize,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws a card filling `rect` with its outline, `label` at the top, `value` in large
+    /// text under it and a sparkline of `trend` in the remaining space, every piece sized to
+    /// the card. Returns `rect`.
+    fn draw_stat_card(
+        &mut self,
+        rect: common::mxcfb_rect,
+        label: &str,
+        value: &str,
+        trend: &[f32],
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws a scrollbar in `track` for a view showing `viewport_len` units of `content_len`
+    /// starting at `offset`. The track is cleared and the thumb, sized in proportion to the
+    /// visible fraction, is filled with `c`. The scrollbar is vertical unless `track` is
+    /// wider than it is tall. Returns the rect of the thumb.
+    fn draw_scrollbar(
+        &mut self,
+        track: common::mxcfb_rect,
+        content_len: usize,
+        viewport_len: usize,
+        offset: usize,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws `count` in `fg` centered on a filled `bg` pill centered on `center`, the pill being
+    /// sized to fit the number. Counts above 99 are shown as "99+". Returns the area of the pill
+    /// clipped to the drawable area.
+    fn draw_badge(
+        &mut self,
+        center: cgmath::Point2<i32>,
+        count: u32,
+        fg: common::color,
+        bg: common::color,
+    ) -> common::mxcfb_rect;
+    /// Draws a clock face of `radius` around `center` with twelve ticks and its hands showing
+    /// `hour:minute`, along with a second hand if `second` is given. Returns the area covered
+    /// by the hands. To update the clock, redraw it over a cleared face and only refresh the
+    /// union of the areas returned for the previous and the new time, since the rest of the
+    /// face stays the same.
+    fn draw_clock(
+        &mut self,
+        center: cgmath::Point2<i32>,
+        radius: usize,
+        hour: u32,
+        minute: u32,
+        second: Option<u32>,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Fills `rect` with a gradient going from `from` to `to` along `direction`, quantized to
+    /// the 16 levels of the panel. `dither_strength` ranges from 0.0 for plain bands to 1.0
+    /// for fully dithered transitions, mid values often read best on e-ink. A `gamma` above
+    /// 1.0 brightens the mid-tones to make the gradient look linear on the panel.
+    fn fill_rect_gradient(
+        &mut self,
+        rect: common::mxcfb_rect,
+        from: common::color,
+        to: common::color,
+        direction: draw::GradientDirection,
+        dither_strength: f32,
+        gamma: Option<f32>,
+    ) -> common::mxcfb_rect;
+    /// Fills rectangle of `height` and `width` at `(y, x)`, clipped to the drawable area
     fn fill_rect(&mut self, y: usize, x: usize, height: usize, width: usize, c: common::color);
+    /// Same as `fill_rect` but returns the clipped rect that was written, which can be
+    /// passed to `partial_refresh` as is, or `None` if the rect is entirely off screen
+    fn fill_rect_checked(
+        &mut self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        c: common::color,
+    ) -> Option<common::mxcfb_rect>;
+    /// Same as `fill_rect` but with the four corners rounded off by quarter circles of
+    /// `radius`, clamped to half the smaller side. Returns the bounding rect of the shape.
+    fn fill_rounded_rect(
+        &mut self,
+        top: u32,
+        left: u32,
+        height: u32,
+        width: u32,
+        radius: u32,
+        c: common::color,
+    ) -> common::mxcfb_rect;
+    /// Replaces every pixel of `region` with its complement, e.g. to highlight a selected item
+    /// without redrawing it. Returns `region` clipped to the drawable area. Inverting black and
+    /// white content keeps it black and white, so it can be refreshed with `WAVEFORM_MODE_DU`.
+    fn invert_rect(&mut self, region: &common::mxcfb_rect) -> common::mxcfb_rect;
     /// Clears the framebuffer however does not perform a refresh
     fn clear(&mut self);
 }
@@ -88,16 +402,39 @@ pub trait FramebufferBase<'a> {
     fn get_fix_screeninfo(device: &std::fs::File) -> screeninfo::FixScreeninfo;
     /// Creates a VarScreeninfo struct and fills it using ioctl
     fn get_var_screeninfo(device: &std::fs::File) -> screeninfo::VarScreeninfo;
+    /// Checks that the structs passed to the EPDC ioctls have the sizes of the known firmware
+    /// and that the screen info describes a usable framebuffer. Doesn't talk to the EPDC, and
+    /// is called once by `new`, which logs an error on mismatch.
+    fn verify_abi(&self) -> Result<(), error::Error>;
+    /// Asks the kernel to wait for a marker that was never submitted, which the EPDC driver
+    /// answers with EINVAL right away. Fails if the kernel doesn't know the ioctl number,
+    /// meaning it expects a differently sized `mxcfb_update_marker_data`. Never called
+    /// implicitly; apps that want the check run it themselves after `new`.
+    fn probe_update_ioctls(&self) -> Result<(), error::Error>;
     /// Makes the proper ioctl call to set the VarScreenInfo.
     /// You must first update the contents of self.var_screen_info
     /// and then call this function.
     fn put_var_screeninfo(&mut self) -> bool;
+    /// Restricts every draw and refresh to `viewport`, for apps that only own part of the
+    /// screen. Pixels outside of it are silently discarded and refresh regions shrunk to fit.
+    /// `None` gives the whole screen back. `RefreshHandle`s keep the viewport that was in
+    /// place when they were obtained.
+    fn set_viewport(&mut self, viewport: Option<common::mxcfb_rect>);
+    /// Returns the viewport set with `set_viewport`, clipped to the screen
+    fn viewport(&self) -> Option<common::mxcfb_rect>;
 }
 
 pub mod refresh;
+/// Every refresh fails with the error the kernel returned for `MXCFB_SEND_UPDATE` when it
+/// drops the update, and every wait with the one of `MXCFB_WAIT_FOR_UPDATE_COMPLETE`. A wait
+/// that timed out reports `ErrorKind::TimedOut`, one that should be retried
+/// `ErrorKind::WouldBlock` or `ErrorKind::Interrupted`.
 pub trait FramebufferRefresh {
     /// Refreshes the entire screen with the provided parameters. If `wait_completion` is
     /// set to true, doesn't return before the refresh has been completed. Returns the marker.
+    ///
+    /// Returns 0 without refreshing if called within `set_min_full_refresh_interval` of the
+    /// previous full refresh.
     fn full_refresh(
         &mut self,
         waveform_mode: common::waveform_mode,
@@ -105,7 +442,23 @@ pub trait FramebufferRefresh {
         dither_mode: common::dither_mode,
         quant_bit: i32,
         wait_completion: bool,
-    ) -> u32;
+    ) -> std::io::Result<u32>;
+
+    /// Fills the screen with white and flashes it clean with a `GC16` `full_refresh` at the
+    /// ambient temperature without dithering, the canonical way to get rid of ghosting,
+    /// e.g. at startup and shutdown. Waits for its completion if `wait` is set. Returns the
+    /// marker.
+    fn clear_screen(&mut self, wait: bool) -> std::io::Result<u32>;
+
+    /// Clears the ghosting on the entire screen without the black/white flash of a `GC16`
+    /// `full_refresh` and waits for its completion. Returns the marker.
+    ///
+    /// This uses the ghost compensating `WAVEFORM_MODE_REAGL`, which leaves slightly more
+    /// residual ghosting behind than a flashing refresh, so an occasional regular
+    /// `full_refresh` is still worthwhile. Falls back to a regular `GC16` `full_refresh` if
+    /// the EPDC rejects the waveform. Subject to `set_min_full_refresh_interval` just like
+    /// `full_refresh`.
+    fn full_refresh_flashless(&mut self) -> std::io::Result<u32>;
 
     /// Refreshes the given `region` with the provided parameters. If `mode` is `DryRun` or
     /// `Wait`, this function won't return before the `DryRun`'s collision_test or
@@ -115,18 +468,18 @@ pub trait FramebufferRefresh {
     ///
     /// Some additional points to note:
     ///
-    ///    1) PxP must process 8x8 pixel blocks, and all pixels in each block
+    /// 1) PxP must process 8x8 pixel blocks, and all pixels in each block
     ///    are considered for auto-waveform mode selection. If the
     ///    update region is not 8x8 aligned, additional unwanted pixels
     ///    will be considered in auto-waveform mode selection.
     ///
-    ///    2) PxP input must be 32-bit aligned, so any update
+    /// 2) PxP input must be 32-bit aligned, so any update
     ///    address not 32-bit aligned must be shifted to meet the
     ///    32-bit alignment.  The PxP will thus end up processing pixels
     ///    outside of the update region to satisfy this alignment restriction,
     ///    which can affect auto-waveform mode selection.
     ///
-    ///    3) If input fails 32-bit alignment, and the resulting expansion
+    /// 3) If input fails 32-bit alignment, and the resulting expansion
     ///    of the processed region would add at least 8 pixels more per
     ///    line than the original update line width, the EPDC would
     ///    cause screen artifacts by incorrectly handling the 8+ pixels
@@ -139,11 +492,194 @@ pub trait FramebufferRefresh {
         temperature: common::display_temp,
         dither_mode: common::dither_mode,
         quant_bit: i32,
-    ) -> u32;
+    ) -> std::io::Result<u32>;
+
+    /// Same as `partial_refresh` but ORs `extra_flags` into the flags of the update, which
+    /// are otherwise only `EPDC_FLAG_TEST_COLLISION` for a `DryRun`.
+    ///
+    /// `EPDC_FLAG_ENABLE_INVERSION` and `EPDC_FLAG_FORCE_MONOCHROME` each apply a lookup to
+    /// the region and can be combined with any mode and with each other, as can
+    /// `EPDC_FLAG_GROUP_UPDATE`. Setting `EPDC_FLAG_TEST_COLLISION` turns the update into a
+    /// test that doesn't change the display, and `EPDC_FLAG_USE_ALT_BUFFER` or
+    /// `EPDC_FLAG_USE_CMAP` additionally need an alternate buffer or a colormap that this
+    /// library doesn't set up, so passing them here isn't supported.
+    fn partial_refresh_flags(
+        &mut self,
+        region: &common::mxcfb_rect,
+        mode: refresh::PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+        extra_flags: u32,
+    ) -> std::io::Result<u32>;
+
+    /// Refreshes `region` asynchronously once it no longer collides with a pending update.
+    /// Up to `max_attempts` `DryRun`s are issued, and after each one reporting a collision
+    /// the latest update submitted before it is waited on, followed by a sleep that doubles
+    /// on every attempt. The refresh is submitted after the last attempt even if it still
+    /// collides. Returns its marker.
+    fn partial_refresh_until_clear(
+        &mut self,
+        region: &common::mxcfb_rect,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+        max_attempts: u32,
+    ) -> std::io::Result<u32>;
+
+    /// Same as `partial_refresh` but first waits on every update submitted by this
+    /// framebuffer and not waited on yet whose region intersects `region`, so that the new
+    /// update never collides with them. Updates stop being tracked once waited on, and only
+    /// the most recent ones are remembered.
+    fn partial_refresh_safe(
+        &mut self,
+        region: &common::mxcfb_rect,
+        mode: refresh::PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> std::io::Result<u32>;
+
+    /// Refreshes the union of `regions` with a single update instead of one per rect, which
+    /// would collide with each other. The union is clamped and grown like the region of
+    /// `partial_refresh`. Returns the same as `partial_refresh`, or 0 if `regions` is empty.
+    fn partial_refresh_batch(
+        &mut self,
+        regions: &[common::mxcfb_rect],
+        mode: refresh::PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> std::io::Result<u32>;
+
+    /// Same as `partial_refresh` but first grows `region` to fully contain every rect of
+    /// `glyphs` it overlaps, so that no character is left partially refreshed. `glyphs` are
+    /// typically obtained from `FramebufferDraw::measure_glyphs` for the text being updated.
+    fn partial_refresh_text(
+        &mut self,
+        region: &common::mxcfb_rect,
+        glyphs: &[common::mxcfb_rect],
+        mode: refresh::PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> std::io::Result<u32>;
+
+    /// Refreshes a non-rectangular shape given as `(y, left, right)` runs of pixels, `right`
+    /// being exclusive, with as few rectangular updates as `refresh::decompose_spans` comes up
+    /// with. Refreshes less than the bounding box of shapes like rings or L-shapes. Returns the
+    /// result of `partial_refresh` for each update.
+    fn partial_refresh_masked(
+        &mut self,
+        spans: &[(u32, u32, u32)],
+        mode: refresh::PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> std::io::Result<Vec<u32>>;
+
+    /// Refreshes `region` with the waveform and temperature bundled in `preset`. When
+    /// `temperature` is `Some`, it is used instead of the temperature of the preset.
+    fn partial_refresh_preset(
+        &mut self,
+        region: &common::mxcfb_rect,
+        mode: refresh::PartialRefreshMode,
+        preset: refresh::RefreshPreset,
+        temperature: Option<common::display_temp>,
+    ) -> std::io::Result<u32>;
+
+    /// Refreshes `region` asynchronously after choosing the waveform from its content and
+    /// returns the marker.
+    ///
+    /// A grid of at most `draw::BILEVEL_MAX_SAMPLES` pixels evenly spread over the region is
+    /// read back. If nearly all of them (`draw::BILEVEL_RATIO`) sit close to pure black or
+    /// pure white, as decided by `draw::is_bilevel`, the content is treated as bilevel (text,
+    /// lines) and refreshed with the fast `WAVEFORM_MODE_DU`. Otherwise it has enough
+    /// intermediate gray levels to warrant the slower, high fidelity `WAVEFORM_MODE_GC16`.
+    fn auto_refresh(&mut self, region: &common::mxcfb_rect) -> std::io::Result<u32>;
+
+    /// Returns the marker that will be assigned to the next refresh
+    fn current_marker(&self) -> u32;
+
+    /// Sets the marker that will be assigned to the next refresh. Subsequent refreshes count
+    /// up from there. When several processes drive the same panel, giving each one a
+    /// non-overlapping marker range prevents them from waiting on each other's updates.
+    ///
+    /// Markers must stay nonzero as the EPDC treats a zero marker as "no marker", therefore a
+    /// `base` of 0 is replaced with 1.
+    fn set_marker_base(&mut self, base: u32);
+
+    /// Puts the marker counter back to 1, so that the markers of the following refreshes are
+    /// predictable, e.g. between the frames of an integration test. Same as
+    /// `set_marker_base(1)`.
+    fn reset_marker(&mut self);
+
+    /// Makes `full_refresh` ignore calls coming within `interval` of the completion of the
+    /// previous full refresh, which avoids flashing the screen twice when several code paths
+    /// decide to clean up the ghosting at once. An `interval` of zero, the default, disables
+    /// the throttling.
+    fn set_min_full_refresh_interval(&mut self, interval: std::time::Duration);
+
+    /// Makes the `partial_refresh` following `every` consecutive partial refreshes a `GC16`
+    /// `full_refresh` instead, which keeps the ghosting of long sessions in check. The
+    /// promoted refresh still returns its marker, and waits if the partial one would have.
+    /// Any full refresh restarts the count. An `every` of 0, the default, disables it.
+    fn set_auto_full_refresh(&mut self, every: u32);
+
+    /// Starts or stops recording a `refresh::RefreshSample` for every refresh. Disabling it
+    /// drops the samples that haven't been taken yet. When disabled, which is the default,
+    /// nothing is recorded.
+    fn set_refresh_profiling(&mut self, enabled: bool);
+
+    /// Returns the samples recorded since the last call and starts a new timeline
+    fn take_refresh_timeline(&mut self) -> Vec<refresh::RefreshSample>;
+
+    /// Starts sending every draw to a copy of the frame instead of the framebuffer, so that
+    /// several of them, such as clearing an element and drawing it again, reach the screen
+    /// at once with `commit_frame`. Does nothing if a frame is already started.
+    fn begin_frame(&mut self);
+
+    /// Copies the span of the frame started with `begin_frame` that differs from what is on
+    /// screen to the framebuffer, ends the frame, and refreshes that span once with
+    /// `WAVEFORM_MODE_GC16_FAST`. Returns the marker, or 0 if nothing changed or no frame was
+    /// started.
+    fn commit_frame(&mut self) -> std::io::Result<u32>;
+
+    /// Copies the span of the frame started with `begin_frame` that differs from what is on
+    /// screen to the framebuffer and refreshes only that span with the provided parameters,
+    /// see `partial_refresh`. Unlike `commit_frame`, the frame goes on. Returns the marker,
+    /// `None` if nothing changed or no frame was started.
+    fn refresh_diff(
+        &mut self,
+        mode: refresh::PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> std::io::Result<Option<u32>>;
+
+    /// Returns a `refresh::RefreshHandle` that can be shared with other threads to submit
+    /// partial refreshes without racing with this framebuffer over the markers
+    fn refresh_handle(&self) -> Result<refresh::RefreshHandle, error::Error>;
 
     /// Takes a marker returned by `partial_refresh` and blocks until that
     /// refresh has been reflected on the display.
     /// Returns the collusion_test result which is supposed to be
     /// related to the collusion information.
-    fn wait_refresh_complete(&mut self, marker: u32) -> u32;
+    fn wait_refresh_complete(&mut self, marker: u32) -> std::io::Result<u32>;
+
+    /// Same as `wait_refresh_complete` but gives up after `timeout`, returning an error of
+    /// kind `ErrorKind::TimedOut`, in case the marker never completes. The wait is done on a
+    /// helper thread, which is left blocked in the kernel when it times out.
+    fn wait_refresh_complete_timeout(
+        &mut self,
+        marker: u32,
+        timeout: std::time::Duration,
+    ) -> std::io::Result<u32>;
 }
@@ -36,7 +36,7 @@ impl ::std::default::Default for mxcfb_update_marker_data {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[repr(C)]
 pub struct mxcfb_alt_buffer_data {
     pub phys_addr: u32,
@@ -51,7 +51,7 @@ impl ::std::default::Default for mxcfb_alt_buffer_data {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[repr(C)]
 pub struct mxcfb_update_data {
     pub update_region: mxcfb_rect,
@@ -0,0 +1,163 @@
+use cgmath;
+use image;
+
+use std::io;
+
+use framebuffer::common;
+use framebuffer::core;
+use framebuffer::refresh::PartialRefreshMode;
+use framebuffer::{FramebufferIO, FramebufferRefresh};
+
+/// Right-angle turns, of an `OffscreenBuffer` when it is copied to the panel or of an image
+/// by `draw::transform_image`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rotation {
+    None,
+    /// The top of the buffer ends up on the right of the panel
+    Clockwise90,
+    UpsideDown,
+    /// The top of the buffer ends up on the left of the panel
+    CounterClockwise90,
+}
+
+/// An 8-bit grayscale buffer drawn to in its own coordinates and rotated only when it is
+/// presented, so that for instance a landscape app can be laid out in landscape while the
+/// rest of the crate keeps addressing the portrait panel
+pub struct OffscreenBuffer {
+    width: u32,
+    height: u32,
+    rotation: Rotation,
+    luma: Vec<u8>,
+}
+
+impl OffscreenBuffer {
+    /// Creates a white buffer of `height` by `width` pixels presented as is
+    pub fn new(height: u32, width: u32) -> OffscreenBuffer {
+        OffscreenBuffer::rotated(cgmath::Vector2::new(width, height), Rotation::None)
+    }
+
+    /// Creates a white buffer of `dims.x` by `dims.y` pixels in its own coordinates, turned
+    /// by `rotation` when presented. A 1872x1404 buffer rotated by 90 degrees covers the
+    /// whole panel.
+    pub fn rotated(dims: cgmath::Vector2<u32>, rotation: Rotation) -> OffscreenBuffer {
+        OffscreenBuffer {
+            width: dims.x,
+            height: dims.y,
+            rotation,
+            luma: vec![0xFF; (dims.x * dims.y) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    pub fn set_pixel(&mut self, y: u32, x: u32, luma: u8) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        self.luma[(y * self.width + x) as usize] = luma;
+    }
+
+    pub fn pixel(&self, y: u32, x: u32) -> Option<u8> {
+        if y >= self.height || x >= self.width {
+            return None;
+        }
+        Some(self.luma[(y * self.width + x) as usize])
+    }
+
+    /// Fills `rect`, in buffer coordinates, with `c`
+    pub fn fill_rect(&mut self, rect: common::mxcfb_rect, c: common::color) {
+        let level = c.as_native()[0];
+        let bottom = ::std::cmp::min(rect.top + rect.height, self.height);
+        let right = ::std::cmp::min(rect.left + rect.width, self.width);
+        for y in rect.top..bottom {
+            for x in rect.left..right {
+                self.luma[(y * self.width + x) as usize] = level;
+            }
+        }
+    }
+
+    /// Fills the whole buffer with `c`
+    pub fn clear(&mut self, c: common::color) {
+        let level = c.as_native()[0];
+        for v in self.luma.iter_mut() {
+            *v = level;
+        }
+    }
+
+    /// Draws `img` at y=top, x=left buffer coordinates with 1:1 scaling
+    pub fn draw_image(&mut self, img: &image::DynamicImage, top: u32, left: u32) {
+        for (x, y, pixel) in img.to_luma().enumerate_pixels() {
+            self.set_pixel(top + y, left + x, pixel.data[0]);
+        }
+    }
+
+    /// Size of the buffer once rotated, as `(height, width)`
+    fn presented_size(&self) -> (u32, u32) {
+        match self.rotation {
+            Rotation::None | Rotation::UpsideDown => (self.height, self.width),
+            Rotation::Clockwise90 | Rotation::CounterClockwise90 => (self.width, self.height),
+        }
+    }
+
+    /// Returns the gray levels of the rotated buffer in row-major order
+    fn rotated_luma(&self) -> Vec<u8> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        if self.rotation == Rotation::None {
+            return self.luma.clone();
+        }
+        let (_, out_width) = self.presented_size();
+        let out_width = out_width as usize;
+        let mut out = vec![0xFF; w * h];
+        for by in 0..h {
+            for bx in 0..w {
+                let (py, px) = match self.rotation {
+                    Rotation::None => (by, bx),
+                    Rotation::Clockwise90 => (bx, h - 1 - by),
+                    Rotation::UpsideDown => (h - 1 - by, w - 1 - bx),
+                    Rotation::CounterClockwise90 => (w - 1 - bx, by),
+                };
+                out[py * out_width + px] = self.luma[by * w + bx];
+            }
+        }
+        out
+    }
+
+    /// Copies the rotated buffer to the framebuffer with its top left corner at y=top,
+    /// x=left panel coordinates. Returns the affected rect.
+    pub fn blit(&self, fb: &mut core::Framebuffer, top: u32, left: u32) -> common::mxcfb_rect {
+        let (height, width) = self.presented_size();
+        fb.write_luma(
+            common::mxcfb_rect {
+                top,
+                left,
+                height,
+                width,
+            },
+            &self.rotated_luma(),
+        )
+    }
+
+    /// Same as `blit`, followed by an asynchronous refresh of the affected rect. Returns the
+    /// marker of the refresh.
+    pub fn present(&self, fb: &mut core::Framebuffer, top: u32, left: u32) -> io::Result<u32> {
+        let written = self.blit(fb, top, left);
+        fb.partial_refresh(
+            &written,
+            PartialRefreshMode::Async,
+            common::waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        )
+    }
+}
@@ -1,11 +1,20 @@
 use libc;
 
-use std::os::unix::io::AsRawFd;
-use std::sync::atomic::Ordering;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use error::Error;
 use framebuffer;
 use framebuffer::common;
 use framebuffer::core;
+use framebuffer::draw;
+use framebuffer::FramebufferDraw;
+use framebuffer::FramebufferIO;
 use framebuffer::mxcfb::*;
 
 macro_rules! max {
@@ -20,12 +29,372 @@ macro_rules! max {
 /// and therefore minimizing collisions through a different mechanism.
 const MIN_SEND_UPDATE_DIMENSION_PX: u32 = 32;
 
+/// Most updates not waited on yet that `partial_refresh_safe` keeps track of
+const MAX_IN_FLIGHT_UPDATES: usize = 64;
+
+/// Sleep before the first retry of `partial_refresh_until_clear`, doubled on every attempt
+const UNTIL_CLEAR_BASE_BACKOFF_MS: u64 = 4;
+
+/// Size of the square blocks `decompose_spans` snaps to. The PxP processes the update
+/// regions in 8x8 blocks anyway, so nothing is gained by being more precise.
+pub const MASK_BLOCK_PX: u32 = 8;
+
+/// Breaks the shape covered by `spans`, each one being a `(y, left, right)` run of pixels
+/// with `right` exclusive, into a small set of rects covering it. The shape is snapped
+/// outwards to a grid of `MASK_BLOCK_PX` blocks, every row of blocks is split into runs and
+/// consecutive rows with the same runs are merged into a single rect.
+pub fn decompose_spans(spans: &[(u32, u32, u32)]) -> Vec<common::mxcfb_rect> {
+    use std::collections::BTreeMap;
+
+    // Covered block columns of each row of blocks
+    let mut rows: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+    for &(y, left, right) in spans {
+        if right <= left {
+            continue;
+        }
+        let (first, last) = (left / MASK_BLOCK_PX, (right - 1) / MASK_BLOCK_PX);
+        let row = rows.entry(y / MASK_BLOCK_PX).or_default();
+        if row.len() <= last as usize {
+            row.resize(last as usize + 1, false);
+        }
+        for block in &mut row[first as usize..last as usize + 1] {
+            *block = true;
+        }
+    }
+
+    let mut done: Vec<common::mxcfb_rect> = Vec::new();
+    // Rects still growing downwards, as (first block column, end block column, rect)
+    let mut open: Vec<(usize, usize, common::mxcfb_rect)> = Vec::new();
+    let mut last_row: Option<u32> = None;
+    for (&row, blocks) in rows.iter() {
+        let mut runs = Vec::new();
+        let mut col = 0;
+        while col < blocks.len() {
+            if !blocks[col] {
+                col += 1;
+                continue;
+            }
+            let start = col;
+            while col < blocks.len() && blocks[col] {
+                col += 1;
+            }
+            runs.push((start, col));
+        }
+
+        let contiguous = last_row.map_or(false, |r| r + 1 == row);
+        let mut still_open = Vec::new();
+        for (start, end, mut rect) in open.drain(..) {
+            match contiguous && runs.contains(&(start, end)) {
+                true => {
+                    rect.height += MASK_BLOCK_PX;
+                    still_open.push((start, end, rect));
+                }
+                false => done.push(rect),
+            }
+        }
+        for &(start, end) in &runs {
+            if !still_open.iter().any(|&(s, e, _)| s == start && e == end) {
+                still_open.push((
+                    start,
+                    end,
+                    common::mxcfb_rect {
+                        top: row * MASK_BLOCK_PX,
+                        left: start as u32 * MASK_BLOCK_PX,
+                        height: MASK_BLOCK_PX,
+                        width: (end - start) as u32 * MASK_BLOCK_PX,
+                    },
+                ));
+            }
+        }
+        open = still_open;
+        last_row = Some(row);
+    }
+    done.extend(open.into_iter().map(|(_, _, rect)| rect));
+    done
+}
+
+/// Samples `region` on an evenly spaced grid and reports whether it looks bilevel, see
+/// `draw::is_bilevel`
+fn is_bilevel_content(fb: &core::Framebuffer, region: &common::mxcfb_rect) -> bool {
+    let (width, height) = (region.width as usize, region.height as usize);
+    let stride = draw::bilevel_sample_stride(height, width);
+    let (top, left) = (region.top as usize, region.left as usize);
+    draw::is_bilevel((0..height).step_by(stride).flat_map(move |y| {
+        (0..width)
+            .step_by(stride)
+            .map(move |x| fb.read_pixel(top + y, left + x).as_native()[0])
+    }))
+}
+
+/// Updates recorded instead of being submitted by a framebuffer created with
+/// `Framebuffer::in_memory`, in the order they were submitted
+pub type UpdateLog = Arc<Mutex<Vec<mxcfb_update_data>>>;
+
+/// Timing information about a single refresh, recorded while profiling is enabled
+#[derive(Clone, Debug)]
+pub struct RefreshSample {
+    pub marker: u32,
+    /// Right after `MXCFB_SEND_UPDATE` returned
+    pub submitted: Instant,
+    /// When the refresh was found to be complete. Only known if it has been waited on.
+    pub completed: Option<Instant>,
+    /// Area of the refreshed region in pixels
+    pub area: u32,
+    pub waveform_mode: u32,
+}
+
+/// Records the submission of `update` as in flight, and in the timeline if profiling is
+/// enabled
+fn record_submission(fb: &mut core::Framebuffer, update: &mxcfb_update_data) {
+    // The oldest updates are forgotten first, they are the most likely to be done by now
+    if fb.in_flight_updates.len() >= MAX_IN_FLIGHT_UPDATES {
+        fb.in_flight_updates.remove(0);
+    }
+    fb.in_flight_updates.push((update.update_marker, update.update_region));
+    if let Some(ref mut timeline) = fb.refresh_timeline {
+        timeline.push(RefreshSample {
+            marker: update.update_marker,
+            submitted: Instant::now(),
+            completed: None,
+            area: update.update_region.width * update.update_region.height,
+            waveform_mode: update.waveform_mode,
+        });
+    }
+}
+
+/// Records the completion of `marker`, which is no longer in flight
+fn record_completion(fb: &mut core::Framebuffer, marker: u32) {
+    fb.in_flight_updates.retain(|&(m, _)| m != marker);
+    if let Some(ref mut timeline) = fb.refresh_timeline {
+        let now = Instant::now();
+        if let Some(sample) = timeline
+            .iter_mut()
+            .rev()
+            .find(|s| s.marker == marker && s.completed.is_none())
+        {
+            sample.completed = Some(now);
+        }
+    }
+}
+
+/// Assigns the next marker to `update` and submits it. Both happen while holding `lock` so
+/// that concurrent submissions can neither get the same marker nor reach the EPDC in an order
+/// different from their markers. Fails with the error of the ioctl if the kernel rejected it.
+/// When given a `log`, the update is appended to it instead of being sent to the EPDC.
+fn submit_update(
+    fd: RawFd,
+    marker: &AtomicU32,
+    lock: &Mutex<()>,
+    log: Option<&UpdateLog>,
+    update: &mut mxcfb_update_data,
+) -> io::Result<()> {
+    let _guard = lock.lock().unwrap();
+    update.update_marker = marker.fetch_add(1, Ordering::SeqCst);
+    if let Some(log) = log {
+        log.lock().unwrap().push(update.clone());
+        return Ok(());
+    }
+    let pt: *const mxcfb_update_data = update;
+    match unsafe { libc::ioctl(fd, common::MXCFB_SEND_UPDATE, pt) } < 0 {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(()),
+    }
+}
+
+/// Clamps `region` to the display and then to `viewport`, growing it to
+/// `MIN_SEND_UPDATE_DIMENSION_PX` first. Returns `None` if nothing is left, in which case
+/// the refresh methods submit nothing and return 0.
+pub fn clamp_update_region(
+    region: &common::mxcfb_rect,
+    viewport: Option<&common::mxcfb_rect>,
+) -> Option<common::mxcfb_rect> {
+    let mut update_region = region.clone();
+
+    // No accounting for this, out of bounds, entirely ignored
+    if update_region.left >= common::DISPLAYWIDTH as u32
+        || update_region.top >= common::DISPLAYHEIGHT as u32
+    {
+        return None;
+    }
+
+    update_region.width = max!(update_region.width, MIN_SEND_UPDATE_DIMENSION_PX);
+    update_region.height = max!(update_region.height, MIN_SEND_UPDATE_DIMENSION_PX);
+
+    // Dont try to refresh OOB horizontally
+    let max_x = update_region.left + update_region.width;
+    if max_x > common::DISPLAYWIDTH as u32 {
+        update_region.width -= max_x - (common::DISPLAYWIDTH as u32);
+    }
+
+    // Dont try to refresh OOB vertically
+    let max_y = update_region.top + update_region.height;
+    if max_y > common::DISPLAYHEIGHT as u32 {
+        update_region.height -= max_y - (common::DISPLAYHEIGHT as u32);
+    }
+
+    // Neither side can have shrunk to 0: both were grown to at least
+    // MIN_SEND_UPDATE_DIMENSION_PX and start on the display. Only regions entirely off it or
+    // outside the viewport are dropped.
+    match viewport {
+        Some(viewport) => update_region.intersection(viewport),
+        None => Some(update_region),
+    }
+}
+
+/// Blocks until the update with `marker` is done and returns its collision_test result.
+/// Updates that went to a `log` are done right away without collisions.
+fn wait_update_complete(fd: RawFd, log: Option<&UpdateLog>, marker: u32) -> io::Result<u32> {
+    if log.is_some() {
+        return Ok(0);
+    }
+    let mut markerdata = mxcfb_update_marker_data {
+        update_marker: marker,
+        collision_test: 0,
+    };
+    match unsafe { libc::ioctl(fd, common::MXCFB_WAIT_FOR_UPDATE_COMPLETE, &mut markerdata) } < 0 {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(markerdata.collision_test),
+    }
+}
+
+/// Copies the tightest box of pixels in which `back` differs from the mapped framebuffer
+/// of `fb` over to it. Returns that box, `None` if they are identical.
+fn blit_changes(fb: &mut core::Framebuffer, back: &[u8]) -> Option<common::mxcfb_rect> {
+    let line_length = fb.fix_screen_info.line_length as usize;
+    let bytespp = (fb.var_screen_info.bits_per_pixel / 8) as usize;
+    let rows = fb.var_screen_info.yres as usize;
+    let row_len = fb.var_screen_info.xres as usize * bytespp;
+    let front = unsafe { ::std::slice::from_raw_parts_mut(fb.frame.data(), fb.frame.len()) };
+
+    // Bounding box of the bytes that changed, in bytes horizontally
+    let (mut top, mut bottom, mut left, mut right) = (rows, 0, row_len, 0);
+    for row in 0..rows {
+        let start = row * line_length;
+        let (b, f) = (&back[start..start + row_len], &front[start..start + row_len]);
+        if let Some(first) = b.iter().zip(f).position(|(b, f)| b != f) {
+            let from_end = b.iter().rev().zip(f.iter().rev()).position(|(b, f)| b != f);
+            top = ::std::cmp::min(top, row);
+            bottom = row + 1;
+            left = ::std::cmp::min(left, first);
+            right = max!(right, row_len - from_end.unwrap_or(0));
+        }
+    }
+    if bottom <= top {
+        return None;
+    }
+
+    // Whole pixels only
+    let (left, right) = (left / bytespp * bytespp, right.div_ceil(bytespp) * bytespp);
+    for row in top..bottom {
+        let start = row * line_length;
+        front[start + left..start + right].copy_from_slice(&back[start + left..start + right]);
+    }
+
+    Some(common::mxcfb_rect {
+        top: top as u32,
+        left: (left / bytespp) as u32,
+        height: (bottom - top) as u32,
+        width: ((right - left) / bytespp) as u32,
+    })
+}
+
+/// A cheaply cloneable handle that can be sent to other threads to refresh the display of the
+/// `Framebuffer` it was obtained from with `refresh_handle`. Refreshes submitted through any
+/// handle or the `Framebuffer` itself are serialized and draw their markers from the same
+/// counter. Refreshes made through a handle aren't recorded while profiling.
+#[derive(Clone)]
+pub struct RefreshHandle {
+    device: Arc<File>,
+    marker: Arc<AtomicU32>,
+    lock: Arc<Mutex<()>>,
+    log: Option<UpdateLog>,
+    viewport: Option<common::mxcfb_rect>,
+    default_temp: common::display_temp,
+}
+
+impl RefreshHandle {
+    /// Same as `FramebufferRefresh::partial_refresh`
+    pub fn partial_refresh(
+        &self,
+        region: &common::mxcfb_rect,
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<u32> {
+        let update_region = match clamp_update_region(region, self.viewport.as_ref()) {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let dither_mode = dither_mode as i32;
+        let mut whole = mxcfb_update_data {
+            update_mode: common::update_mode::UPDATE_MODE_PARTIAL as u32,
+            waveform_mode: waveform_mode as u32,
+            temp: temperature.resolve(self.default_temp) as i32,
+            flags: match mode {
+                PartialRefreshMode::DryRun => common::EPDC_FLAG_TEST_COLLISION as u32,
+                _ => 0,
+            },
+            quant_bit: common::validate_quant_bit(dither_mode, quant_bit),
+            dither_mode,
+            update_region,
+            ..Default::default()
+        };
+        let fd = self.device.as_raw_fd();
+        submit_update(fd, &self.marker, &self.lock, self.log.as_ref(), &mut whole)?;
+        match mode {
+            PartialRefreshMode::Wait | PartialRefreshMode::DryRun => {
+                wait_update_complete(fd, self.log.as_ref(), whole.update_marker)
+            }
+            PartialRefreshMode::Async => Ok(whole.update_marker),
+        }
+    }
+
+    /// Same as `FramebufferRefresh::wait_refresh_complete`
+    pub fn wait_refresh_complete(&self, marker: u32) -> io::Result<u32> {
+        wait_update_complete(self.device.as_raw_fd(), self.log.as_ref(), marker)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PartialRefreshMode {
     DryRun,
     Async,
     Wait,
 }
 
+/// Commonly used combinations of the refresh parameters, see `partial_refresh_preset`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RefreshPreset {
+    /// `DU` as used by xochitl for pen strokes
+    Drawing,
+    /// `GC16_FAST`, the medium fidelity waveform xochitl uses for its UI
+    UI,
+    /// High fidelity `GC16` for images and other content with many gray levels
+    Image,
+    /// `GLR16`, the quickest black/white transitions at the cost of some leftovers
+    Fast,
+}
+
+impl RefreshPreset {
+    pub fn waveform_mode(&self) -> common::waveform_mode {
+        match *self {
+            RefreshPreset::Drawing => common::waveform_mode::WAVEFORM_MODE_DU,
+            RefreshPreset::UI => common::waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            RefreshPreset::Image => common::waveform_mode::WAVEFORM_MODE_GC16,
+            RefreshPreset::Fast => common::waveform_mode::WAVEFORM_MODE_GLR16,
+        }
+    }
+
+    pub fn temperature(&self) -> common::display_temp {
+        match *self {
+            RefreshPreset::Image => common::display_temp::TEMP_USE_AMBIENT,
+            _ => common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+        }
+    }
+}
+
 impl<'a> framebuffer::FramebufferRefresh for core::Framebuffer<'a> {
     fn full_refresh(
         &mut self,
@@ -34,48 +403,103 @@ impl<'a> framebuffer::FramebufferRefresh for core::Framebuffer<'a> {
         dither_mode: common::dither_mode,
         quant_bit: i32,
         wait_completion: bool,
-    ) -> u32 {
-        let screen = common::mxcfb_rect {
-            top: 0,
-            left: 0,
-            height: self.var_screen_info.yres,
-            width: self.var_screen_info.xres,
-        };
-        let whole = mxcfb_update_data {
+    ) -> io::Result<u32> {
+        if let Some(last) = self.last_full_refresh {
+            if last.elapsed() < self.min_full_refresh_interval {
+                debug!("Ignoring a full_refresh(..) issued too soon after the previous one");
+                return Ok(0);
+            }
+        }
+
+        let screen = self.drawable_rect();
+        let dither_mode = dither_mode as i32;
+        let mut whole = mxcfb_update_data {
             update_mode: common::update_mode::UPDATE_MODE_FULL as u32,
-            update_marker: *self.marker.get_mut() as u32,
             waveform_mode: waveform_mode as u32,
-            temp: temperature as i32,
+            temp: temperature.resolve(self.default_temp) as i32,
             flags: 0,
-            quant_bit,
-            dither_mode: dither_mode as i32,
+            quant_bit: common::validate_quant_bit(dither_mode, quant_bit),
+            dither_mode,
             update_region: screen,
             ..Default::default()
         };
-        self.marker.swap(whole.update_marker + 1, Ordering::Relaxed);
+        submit_update(
+            self.device.as_raw_fd(),
+            &self.marker,
+            &self.refresh_lock,
+            self.update_log.as_ref(),
+            &mut whole,
+        )?;
+        record_submission(self, &whole);
 
-        let pt: *const mxcfb_update_data = &whole;
-        unsafe {
-            libc::ioctl(self.device.as_raw_fd(), common::MXCFB_SEND_UPDATE, pt);
+        self.partial_refreshes_since_full = 0;
+        if wait_completion {
+            self.wait_refresh_complete(whole.update_marker)?;
         }
+        self.last_full_refresh = Some(Instant::now());
+        Ok(whole.update_marker)
+    }
 
-        if wait_completion {
-            let mut markerdata = mxcfb_update_marker_data {
-                update_marker: whole.update_marker,
-                collision_test: 0,
-            };
-            unsafe {
-                if libc::ioctl(
-                    self.device.as_raw_fd(),
-                    common::MXCFB_WAIT_FOR_UPDATE_COMPLETE,
-                    &mut markerdata,
-                ) < 0
-                {
-                    warn!("WAIT_FOR_UPDATE_COMPLETE failed after a full_refresh(..)");
-                }
+    fn clear_screen(&mut self, wait: bool) -> io::Result<u32> {
+        let screen = self.drawable_rect();
+        self.fill_rect(
+            screen.top as usize,
+            screen.left as usize,
+            screen.height as usize,
+            screen.width as usize,
+            common::color::WHITE,
+        );
+        self.full_refresh(
+            common::waveform_mode::WAVEFORM_MODE_GC16,
+            common::display_temp::TEMP_USE_AMBIENT,
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+            wait,
+        )
+    }
+
+    fn full_refresh_flashless(&mut self) -> io::Result<u32> {
+        if let Some(last) = self.last_full_refresh {
+            if last.elapsed() < self.min_full_refresh_interval {
+                debug!("Ignoring a full_refresh_flashless() issued too soon after the last one");
+                return Ok(0);
             }
         }
-        whole.update_marker
+
+        let mut whole = mxcfb_update_data {
+            update_mode: common::update_mode::UPDATE_MODE_FULL as u32,
+            waveform_mode: common::waveform_mode::WAVEFORM_MODE_REAGL as u32,
+            temp: common::display_temp::TEMP_USE_AMBIENT as i32,
+            flags: 0,
+            quant_bit: 0,
+            dither_mode: common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH as i32,
+            update_region: self.drawable_rect(),
+            ..Default::default()
+        };
+        let res = submit_update(
+            self.device.as_raw_fd(),
+            &self.marker,
+            &self.refresh_lock,
+            self.update_log.as_ref(),
+            &mut whole,
+        );
+        if let Err(e) = res {
+            warn!("WAVEFORM_MODE_REAGL was rejected ({0}), falling back to a full_refresh(..)", e);
+            return self.full_refresh(
+                common::waveform_mode::WAVEFORM_MODE_GC16,
+                common::display_temp::TEMP_USE_AMBIENT,
+                common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+                true,
+            );
+        }
+
+        record_submission(self, &whole);
+        self.partial_refreshes_since_full = 0;
+
+        self.wait_refresh_complete(whole.update_marker)?;
+        self.last_full_refresh = Some(Instant::now());
+        Ok(whole.update_marker)
     }
 
     fn partial_refresh(
@@ -86,89 +510,401 @@ impl<'a> framebuffer::FramebufferRefresh for core::Framebuffer<'a> {
         temperature: common::display_temp,
         dither_mode: common::dither_mode,
         quant_bit: i32,
-    ) -> u32 {
-        let mut update_region = region.clone();
-
-        // No accounting for this, out of bounds, entirely ignored
-        if update_region.left >= common::DISPLAYWIDTH as u32
-            || update_region.top >= common::DISPLAYHEIGHT as u32
-        {
-            return 0;
-        }
-
-        update_region.width = max!(update_region.width, MIN_SEND_UPDATE_DIMENSION_PX);
-        update_region.height = max!(update_region.height, MIN_SEND_UPDATE_DIMENSION_PX);
+    ) -> io::Result<u32> {
+        self.partial_refresh_flags(
+            region,
+            mode,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+            0,
+        )
+    }
 
-        // Dont try to refresh OOB horizontally
-        let max_x = update_region.left + update_region.width;
-        if max_x > common::DISPLAYWIDTH as u32 {
-            update_region.width -= max_x - (common::DISPLAYWIDTH as u32);
-        }
+    fn partial_refresh_flags(
+        &mut self,
+        region: &common::mxcfb_rect,
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+        extra_flags: u32,
+    ) -> io::Result<u32> {
+        let update_region = match clamp_update_region(region, self.viewport.as_ref()) {
+            Some(r) => r,
+            None => return Ok(0),
+        };
 
-        // Dont try to refresh OOB vertically
-        let max_y = update_region.top + update_region.height;
-        if max_y > common::DISPLAYHEIGHT as u32 {
-            update_region.height -= max_y - (common::DISPLAYHEIGHT as u32);
+        let every = self.auto_full_refresh_every;
+        if every > 0 && mode != PartialRefreshMode::DryRun {
+            if self.partial_refreshes_since_full >= every {
+                let marker = self.full_refresh(
+                    common::waveform_mode::WAVEFORM_MODE_GC16,
+                    temperature,
+                    common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                    0,
+                    mode == PartialRefreshMode::Wait,
+                )?;
+                // A full refresh throttled by `set_min_full_refresh_interval` returns 0, in
+                // which case the partial refresh goes ahead as requested
+                if marker != 0 {
+                    return Ok(marker);
+                }
+            }
+            self.partial_refreshes_since_full += 1;
         }
 
-        let whole = mxcfb_update_data {
+        let dither_mode = dither_mode as i32;
+        let mut whole = mxcfb_update_data {
             update_mode: common::update_mode::UPDATE_MODE_PARTIAL as u32,
-            update_marker: *self.marker.get_mut() as u32,
             waveform_mode: waveform_mode as u32,
-            temp: temperature as i32,
-            flags: match mode {
+            temp: temperature.resolve(self.default_temp) as i32,
+            flags: extra_flags | match mode {
                 PartialRefreshMode::DryRun => common::EPDC_FLAG_TEST_COLLISION as u32,
                 _ => 0,
             },
-            quant_bit,
-            dither_mode: dither_mode as i32,
+            quant_bit: common::validate_quant_bit(dither_mode, quant_bit),
+            dither_mode,
             update_region,
             ..Default::default()
         };
-        self.marker.swap(whole.update_marker + 1, Ordering::Relaxed);
-
-        let pt: *const mxcfb_update_data = &whole;
-        unsafe {
-            libc::ioctl(self.device.as_raw_fd(), common::MXCFB_SEND_UPDATE, pt);
-        }
+        submit_update(
+            self.device.as_raw_fd(),
+            &self.marker,
+            &self.refresh_lock,
+            self.update_log.as_ref(),
+            &mut whole,
+        )?;
+        record_submission(self, &whole);
 
         match mode {
             PartialRefreshMode::Wait | PartialRefreshMode::DryRun => {
-                let mut markerdata = mxcfb_update_marker_data {
-                    update_marker: whole.update_marker,
-                    collision_test: 0,
-                };
-                unsafe {
-                    if libc::ioctl(
-                        self.device.as_raw_fd(),
-                        common::MXCFB_WAIT_FOR_UPDATE_COMPLETE,
-                        &mut markerdata,
-                    ) < 0
-                    {
-                        warn!("WAIT_FOR_UPDATE_COMPLETE failed after a partial_refresh(..)");
-                    }
-                }
-                markerdata.collision_test
+                self.wait_refresh_complete(whole.update_marker)
+            }
+            PartialRefreshMode::Async => Ok(whole.update_marker),
+        }
+    }
+
+    fn partial_refresh_safe(
+        &mut self,
+        region: &common::mxcfb_rect,
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<u32> {
+        let update_region = match clamp_update_region(region, self.viewport.as_ref()) {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let overlapping: Vec<u32> = self.in_flight_updates
+            .iter()
+            .filter(|&&(_, r)| r.intersection(&update_region).is_some())
+            .map(|&(marker, _)| marker)
+            .collect();
+        for marker in overlapping {
+            self.wait_refresh_complete(marker)?;
+        }
+        self.partial_refresh(
+            &update_region,
+            mode,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+        )
+    }
+
+    fn partial_refresh_batch(
+        &mut self,
+        regions: &[common::mxcfb_rect],
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<u32> {
+        let bounds = match regions.split_first() {
+            Some((first, rest)) => rest.iter().fold(*first, |acc, r| acc.union(r)),
+            None => return Ok(0),
+        };
+        self.partial_refresh(
+            &bounds,
+            mode,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+        )
+    }
+
+    fn partial_refresh_until_clear(
+        &mut self,
+        region: &common::mxcfb_rect,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+        max_attempts: u32,
+    ) -> io::Result<u32> {
+        let mut backoff = Duration::from_millis(UNTIL_CLEAR_BASE_BACKOFF_MS);
+        for attempt in 0..max_attempts {
+            // The dry run itself takes a marker, so the update before it is the latest one
+            let latest = self.current_marker().wrapping_sub(1);
+            let collision_test = self.partial_refresh(
+                region,
+                PartialRefreshMode::DryRun,
+                waveform_mode,
+                temperature,
+                dither_mode,
+                quant_bit,
+            )?;
+            if collision_test == 0 {
+                break;
+            }
+            debug!(
+                "partial_refresh_until_clear(..) collided on attempt {0}, waiting for {1}",
+                attempt + 1,
+                latest
+            );
+            if latest != 0 {
+                self.wait_refresh_complete(latest)?;
+            }
+            ::std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+        self.partial_refresh(
+            region,
+            PartialRefreshMode::Async,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+        )
+    }
+
+    fn partial_refresh_preset(
+        &mut self,
+        region: &common::mxcfb_rect,
+        mode: PartialRefreshMode,
+        preset: RefreshPreset,
+        temperature: Option<common::display_temp>,
+    ) -> io::Result<u32> {
+        self.partial_refresh(
+            region,
+            mode,
+            preset.waveform_mode(),
+            temperature.unwrap_or_else(|| preset.temperature()),
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        )
+    }
+
+    fn partial_refresh_text(
+        &mut self,
+        region: &common::mxcfb_rect,
+        glyphs: &[common::mxcfb_rect],
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<u32> {
+        // Growing the region can make it reach glyphs it didn't overlap before, e.g. on the
+        // line above, so keep going until it stops growing
+        let mut snapped = *region;
+        loop {
+            let grown = glyphs
+                .iter()
+                .filter(|g| g.intersection(&snapped).is_some())
+                .fold(snapped, |acc, g| acc.union(g));
+            if grown == snapped {
+                break;
             }
-            PartialRefreshMode::Async => whole.update_marker,
+            snapped = grown;
+        }
+        self.partial_refresh(
+            &snapped,
+            mode,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+        )
+    }
+
+    fn partial_refresh_masked(
+        &mut self,
+        spans: &[(u32, u32, u32)],
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<Vec<u32>> {
+        decompose_spans(spans)
+            .iter()
+            .map(|rect| {
+                self.partial_refresh(
+                    rect,
+                    mode,
+                    waveform_mode,
+                    temperature,
+                    dither_mode,
+                    quant_bit,
+                )
+            })
+            .collect()
+    }
+
+    fn auto_refresh(&mut self, region: &common::mxcfb_rect) -> io::Result<u32> {
+        let waveform = match is_bilevel_content(self, region) {
+            true => common::waveform_mode::WAVEFORM_MODE_DU,
+            false => common::waveform_mode::WAVEFORM_MODE_GC16,
+        };
+        self.partial_refresh(
+            region,
+            PartialRefreshMode::Async,
+            waveform,
+            common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        )
+    }
+
+    fn current_marker(&self) -> u32 {
+        self.marker.load(Ordering::Relaxed)
+    }
+
+    fn set_marker_base(&mut self, base: u32) {
+        if base == 0 {
+            warn!("Update markers need to be nonzero, using 1 as the marker base instead of 0");
+        }
+        self.marker.store(max!(base, 1), Ordering::Relaxed);
+    }
+
+    fn reset_marker(&mut self) {
+        self.set_marker_base(1);
+    }
+
+    fn set_min_full_refresh_interval(&mut self, interval: Duration) {
+        self.min_full_refresh_interval = interval;
+    }
+
+    fn set_auto_full_refresh(&mut self, every: u32) {
+        self.auto_full_refresh_every = every;
+        self.partial_refreshes_since_full = 0;
+    }
+
+    fn set_refresh_profiling(&mut self, enabled: bool) {
+        self.refresh_timeline = match enabled {
+            true => Some(self.refresh_timeline.take().unwrap_or_default()),
+            false => None,
+        };
+    }
+
+    fn take_refresh_timeline(&mut self) -> Vec<RefreshSample> {
+        match self.refresh_timeline {
+            Some(ref mut timeline) => ::std::mem::take(timeline),
+            None => Vec::new(),
         }
     }
 
-    fn wait_refresh_complete(&mut self, marker: u32) -> u32 {
-        let mut markerdata = mxcfb_update_marker_data {
-            update_marker: marker,
-            collision_test: 0,
+    fn refresh_handle(&self) -> Result<RefreshHandle, Error> {
+        Ok(RefreshHandle {
+            device: Arc::new(self.device.try_clone()?),
+            marker: Arc::clone(&self.marker),
+            lock: Arc::clone(&self.refresh_lock),
+            log: self.update_log.clone(),
+            viewport: self.viewport,
+            default_temp: self.default_temp,
+        })
+    }
+
+    fn begin_frame(&mut self) {
+        if self.back_buffer.is_some() {
+            return;
+        }
+        let frame = unsafe { ::std::slice::from_raw_parts(self.frame.data(), self.frame.len()) };
+        self.back_buffer = Some(frame.to_vec());
+    }
+
+    fn commit_frame(&mut self) -> io::Result<u32> {
+        let back = match self.back_buffer.take() {
+            Some(back) => back,
+            None => return Ok(0),
+        };
+        let changed = match blit_changes(self, &back) {
+            Some(changed) => changed,
+            None => return Ok(0),
+        };
+        self.partial_refresh(
+            &changed,
+            PartialRefreshMode::Async,
+            common::waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+            common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        )
+    }
+
+    fn refresh_diff(
+        &mut self,
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<Option<u32>> {
+        let back = match self.back_buffer.take() {
+            Some(back) => back,
+            None => return Ok(None),
         };
-        unsafe {
-            if libc::ioctl(
-                self.device.as_raw_fd(),
-                common::MXCFB_WAIT_FOR_UPDATE_COMPLETE,
-                &mut markerdata,
-            ) < 0
-            {
-                warn!("WAIT_FOR_UPDATE_COMPLETE failed");
+        let changed = blit_changes(self, &back);
+        self.back_buffer = Some(back);
+        match changed {
+            Some(changed) => self.partial_refresh(
+                &changed,
+                mode,
+                waveform_mode,
+                temperature,
+                dither_mode,
+                quant_bit,
+            ).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn wait_refresh_complete(&mut self, marker: u32) -> io::Result<u32> {
+        let collision_test =
+            wait_update_complete(self.device.as_raw_fd(), self.update_log.as_ref(), marker)?;
+        record_completion(self, marker);
+        return Ok(collision_test);
+    }
+
+    fn wait_refresh_complete_timeout(&mut self, marker: u32, timeout: Duration) -> io::Result<u32> {
+        // The waiting thread owns a duplicate of the descriptor so that it stays valid even
+        // if it outlives this framebuffer
+        let device = self.device.try_clone()?;
+        let log = self.update_log.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(wait_update_complete(device.as_raw_fd(), log.as_ref(), marker));
+        });
+
+        let collision_test = match receiver.recv_timeout(timeout) {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("marker {0} didn't complete in {1:?}", marker, timeout),
+                ))
             }
         };
-        return markerdata.collision_test;
+        record_completion(self, marker);
+        Ok(collision_test)
     }
 }
@@ -0,0 +1,72 @@
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use framebuffer::common;
+use framebuffer::core;
+use framebuffer::refresh::PartialRefreshMode;
+use framebuffer::{FramebufferDraw, FramebufferRefresh};
+
+/// A cheaply cloneable `Framebuffer` that can be drawn to and refreshed from several threads.
+///
+/// Every call locks the whole framebuffer for its duration, so a draw and the refresh that
+/// follows it from another thread never interleave within a single call. Draws made from
+/// different threads may still land in any order between calls; hold the guard returned by
+/// `lock` to draw and refresh as one step. Don't call the forwarding methods while holding
+/// that guard on the same thread, as the lock isn't reentrant.
+///
+/// Refreshes draw their markers from the same atomic counter whichever thread submits them,
+/// so two concurrent `partial_refresh` calls are always given distinct markers.
+#[derive(Clone)]
+pub struct SharedFramebuffer<'a> {
+    inner: Arc<Mutex<core::Framebuffer<'a>>>,
+}
+
+impl<'a> SharedFramebuffer<'a> {
+    pub fn new(framebuffer: core::Framebuffer<'a>) -> SharedFramebuffer<'a> {
+        SharedFramebuffer {
+            inner: Arc::new(Mutex::new(framebuffer)),
+        }
+    }
+
+    /// Locks the framebuffer for exclusive use until the guard is dropped
+    pub fn lock(&self) -> MutexGuard<core::Framebuffer<'a>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Same as `FramebufferDraw::fill_rect`
+    pub fn fill_rect(&self, y: usize, x: usize, height: usize, width: usize, c: common::color) {
+        self.lock().fill_rect(y, x, height, width, c)
+    }
+
+    /// Same as `FramebufferDraw::draw_text`
+    pub fn draw_text(
+        &self,
+        y: usize,
+        x: usize,
+        text: String,
+        size: usize,
+        col: common::color,
+    ) -> common::mxcfb_rect {
+        self.lock().draw_text(y, x, text, size, col)
+    }
+
+    /// Same as `FramebufferRefresh::partial_refresh`
+    pub fn partial_refresh(
+        &self,
+        region: &common::mxcfb_rect,
+        mode: PartialRefreshMode,
+        waveform_mode: common::waveform_mode,
+        temperature: common::display_temp,
+        dither_mode: common::dither_mode,
+        quant_bit: i32,
+    ) -> io::Result<u32> {
+        self.lock().partial_refresh(
+            region,
+            mode,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+        )
+    }
+}
@@ -0,0 +1,127 @@
+use framebuffer::common::mxcfb_rect;
+use framebuffer::core;
+use framebuffer::draw::EraseOrColor;
+use framebuffer::FramebufferDraw;
+
+/// Highest pressure reported by the Wacom digitizer, a pen resting with no force reports 0
+pub const MAX_RAW_PRESSURE: u16 = 4095;
+
+/// Maps the pressure of a pen sample, normalized to `0.0..=1.0`, to a fraction of the widest
+/// stroke width
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum PressureCurve {
+    /// Width grows in proportion to the pressure, the default
+    #[default]
+    Linear,
+    /// Width grows as the pressure raised to the given power. Below 1.0 a light touch
+    /// already gives a wide stroke, above 1.0 it takes pressing hard.
+    Exponential(f32),
+    /// Width linearly interpolated between `(pressure, width)` control points, both in
+    /// `0.0..=1.0` and sorted by pressure. Pressures outside of the points take the width
+    /// of the closest one.
+    Custom(Vec<(f32, f32)>),
+}
+
+impl PressureCurve {
+    /// A light touch is enough for a wide stroke
+    pub fn soft() -> PressureCurve {
+        PressureCurve::Exponential(0.5)
+    }
+
+    /// Wide strokes require pressing hard
+    pub fn firm() -> PressureCurve {
+        PressureCurve::Exponential(2.0)
+    }
+
+    /// Returns the fraction of the widest stroke for the normalized `pressure`
+    pub fn apply(&self, pressure: f32) -> f32 {
+        let pressure = pressure.max(0.0).min(1.0);
+        let factor = match *self {
+            PressureCurve::Linear => pressure,
+            PressureCurve::Exponential(power) => pressure.powf(power),
+            PressureCurve::Custom(ref points) => {
+                match points.iter().position(|&(p, _)| p >= pressure) {
+                    None => points.last().map_or(pressure, |&(_, w)| w),
+                    Some(0) => points[0].1,
+                    Some(i) => {
+                        let ((p0, w0), (p1, w1)) = (points[i - 1], points[i]);
+                        match p1 > p0 {
+                            true => w0 + (w1 - w0) * (pressure - p0) / (p1 - p0),
+                            false => w1,
+                        }
+                    }
+                }
+            }
+        };
+        factor.max(0.0).min(1.0)
+    }
+}
+
+/// Draws a pen stroke one sample at a time, each segment as wide as the `PressureCurve`
+/// makes the pressure of the sample ending it
+pub struct StrokeBuilder {
+    ink: EraseOrColor,
+    curve: PressureCurve,
+    min_width: usize,
+    max_width: usize,
+    last: Option<(i32, i32)>,
+}
+
+impl StrokeBuilder {
+    /// Creates a stroke with a linear curve and widths between 1 and 4 pixels
+    pub fn new(ink: EraseOrColor) -> StrokeBuilder {
+        StrokeBuilder {
+            ink,
+            curve: PressureCurve::default(),
+            min_width: 1,
+            max_width: 4,
+            last: None,
+        }
+    }
+
+    pub fn with_curve(mut self, curve: PressureCurve) -> StrokeBuilder {
+        self.curve = curve;
+        self
+    }
+
+    /// Sets the widths drawn at no and full pressure. `min` is raised to 1 so that light
+    /// samples never produce invisible segments.
+    pub fn with_width_range(mut self, min: usize, max: usize) -> StrokeBuilder {
+        self.min_width = ::std::cmp::max(min, 1);
+        self.max_width = ::std::cmp::max(max, self.min_width);
+        self
+    }
+
+    pub fn ink(&self) -> EraseOrColor {
+        self.ink
+    }
+
+    /// Width of a segment drawn with the raw `pressure`, from 0 to `MAX_RAW_PRESSURE`
+    pub fn width_for(&self, pressure: u16) -> usize {
+        let normalized = pressure as f32 / MAX_RAW_PRESSURE as f32;
+        let span = (self.max_width - self.min_width) as f32;
+        self.min_width + (span * self.curve.apply(normalized)).round() as usize
+    }
+
+    /// Extends the stroke to `(y, x)` and returns the area drawn. The first sample of a
+    /// stroke only sets where it starts and returns `None`.
+    pub fn add_sample(
+        &mut self,
+        fb: &mut core::Framebuffer,
+        y: i32,
+        x: i32,
+        pressure: u16,
+    ) -> Option<mxcfb_rect> {
+        let rect = self.last.map(|(y0, x0)| {
+            let width = self.width_for(pressure);
+            fb.draw_line_ink(y0, x0, y, x, width, self.ink)
+        });
+        self.last = Some((y, x));
+        rect
+    }
+
+    /// Ends the stroke, the next sample starts a new one
+    pub fn end(&mut self) {
+        self.last = None;
+    }
+}
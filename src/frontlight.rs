@@ -0,0 +1,64 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use error::Error;
+
+/// Returns the sysfs directory of the first backlight device, which drives the frontlight
+/// on the models that have one
+fn device_path() -> Result<PathBuf, Error> {
+    let entries = match fs::read_dir("/sys/class/backlight") {
+        Ok(entries) => entries,
+        Err(_) => return Err(Error::Unsupported("frontlight")),
+    };
+    match entries.filter_map(|e| e.ok()).next() {
+        Some(entry) => Ok(entry.path()),
+        None => Err(Error::Unsupported("frontlight")),
+    }
+}
+
+fn read_attribute(attr: &str) -> Result<u32, Error> {
+    let mut data = String::new();
+    let path = device_path()?.join(attr);
+    match File::open(&path) {
+        Err(e) => Err(Error::Io(e)),
+        Ok(ref mut f) => match f.read_to_string(&mut data)? {
+            0 => Err(Error::InvalidData(format!("'{0}' is empty", attr))),
+            _ => match data.trim().parse::<u32>() {
+                Ok(r) => Ok(r),
+                Err(_) => Err(Error::InvalidData(format!(
+                    "Unable to parse the contents of '{0}' during a frontlight query",
+                    attr
+                ))),
+            },
+        },
+    }
+}
+
+/// $ cat /sys/class/backlight/*/max_brightness
+/// 2047
+pub fn max_brightness() -> Result<u32, Error> {
+    read_attribute("max_brightness")
+}
+
+/// Returns the current brightness scaled to 0-255, 0 being off
+pub fn get_brightness() -> Result<u8, Error> {
+    let max = max_brightness()?;
+    if max == 0 {
+        return Err(Error::InvalidData(
+            "Frontlight reports a max_brightness of 0".to_owned(),
+        ));
+    }
+    let curr = read_attribute("brightness")?;
+    Ok((::std::cmp::min(curr, max) as u64 * 255 / max as u64) as u8)
+}
+
+/// Sets the brightness from `level` scaled to 0-255, 0 turning the frontlight off
+pub fn set_brightness(level: u8) -> Result<(), Error> {
+    let max = max_brightness()?;
+    let value = level as u64 * max as u64 / 255;
+    let path = device_path()?.join("brightness");
+    let mut f = File::create(&path)?;
+    f.write_all(format!("{0}", value).as_bytes())?;
+    Ok(())
+}
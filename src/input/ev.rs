@@ -44,3 +44,43 @@ pub fn start_evdev<H: input::EvdevHandler + Send>(
         }
     })
 }
+
+/// Input devices of the reMarkable: the Wacom digitizer, the touchscreen and the buttons
+pub const DEFAULT_INPUT_DEVICES: [&str; 3] =
+    ["/dev/input/event0", "/dev/input/event1", "/dev/input/event2"];
+
+/// Reads a set of input devices, each on a thread of its own, and sends the events decoded
+/// from all of them to a single `std::sync::mpsc` channel
+pub struct EvDevContext {
+    threads: Vec<std::thread::JoinHandle<()>>,
+}
+
+impl EvDevContext {
+    /// Starts reading the devices at `paths`. Events are decoded by a `UnifiedInputHandler`
+    /// per device, so pen positions and touches come out in panel coordinates. The reader
+    /// threads keep running until the process exits.
+    pub fn start(paths: &[&str]) -> (EvDevContext, std::sync::mpsc::Receiver<input::InputEvent>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let threads = paths
+            .iter()
+            .map(|path| {
+                // The handler lives as long as the thread reading into it: forever
+                let handler: &'static mut input::UnifiedInputHandler<'static> = Box::leak(
+                    Box::new(input::UnifiedInputHandler::with_channel(sender.clone())),
+                );
+                start_evdev(path.to_string(), handler)
+            })
+            .collect();
+        (EvDevContext { threads }, receiver)
+    }
+
+    /// Same as `start` with `DEFAULT_INPUT_DEVICES`
+    pub fn start_default() -> (EvDevContext, std::sync::mpsc::Receiver<input::InputEvent>) {
+        EvDevContext::start(&DEFAULT_INPUT_DEVICES)
+    }
+
+    /// Number of devices being read
+    pub fn device_count(&self) -> usize {
+        self.threads.len()
+    }
+}
@@ -1,6 +1,5 @@
 use input::{InputEvent, UnifiedInputHandler};
 use evdev::raw::input_event;
-use rb::RbProducer;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum PhysicalButton {
@@ -60,9 +59,7 @@ impl<'a> UnifiedInputHandler<'a> {
                     true => GPIOEvent::Press { button: p },
                     false => GPIOEvent::Unpress { button: p },
                 };
-                self.ringbuffer
-                    .write(&[InputEvent::GPIO { event }])
-                    .unwrap();
+                self.emit(InputEvent::GPIO { event });
             }
             _ => {
                 // Shouldn't happen
@@ -37,22 +37,64 @@ unsafe impl<'a> Send for UnifiedInputHandler<'a> {}
 unsafe impl<'a> Sync for UnifiedInputHandler<'a> {}
 
 use rb;
+use rb::RbProducer;
+use std::sync::mpsc;
+
+/// Where a `UnifiedInputHandler` sends the events it decodes
+pub enum InputSink<'a> {
+    RingBuffer(&'a rb::Producer<InputEvent>),
+    Channel(mpsc::Sender<InputEvent>),
+}
+
 pub struct UnifiedInputHandler<'a> {
     pub wacom: wacom::WacomState,
     pub gpio: gpio::GPIOState,
     pub mt: multitouch::MultitouchState,
-    pub ringbuffer: &'a rb::Producer<InputEvent>,
+    pub sink: InputSink<'a>,
 }
 
 impl<'a> UnifiedInputHandler<'a> {
     pub fn new(ringbuffer: &rb::Producer<InputEvent>) -> UnifiedInputHandler {
+        return UnifiedInputHandler::with_sink(InputSink::RingBuffer(ringbuffer));
+    }
+
+    /// Creates a handler sending its events to `sender` instead of a ring buffer
+    pub fn with_channel(sender: mpsc::Sender<InputEvent>) -> UnifiedInputHandler<'static> {
+        return UnifiedInputHandler::with_sink(InputSink::Channel(sender));
+    }
+
+    fn with_sink(sink: InputSink) -> UnifiedInputHandler {
         return UnifiedInputHandler {
             gpio: gpio::GPIOState::new(),
             wacom: wacom::WacomState::new(),
             mt: multitouch::MultitouchState::new(),
-            ringbuffer,
+            sink,
         };
     }
+
+    /// The ring buffer the events are written to, `None` for a handler created with
+    /// `with_channel`. Replaces the `ringbuffer` field, which became `sink`.
+    #[deprecated(note = "match on `sink` instead")]
+    pub fn ringbuffer(&self) -> Option<&'a rb::Producer<InputEvent>> {
+        match self.sink {
+            InputSink::RingBuffer(ringbuffer) => Some(ringbuffer),
+            InputSink::Channel(_) => None,
+        }
+    }
+
+    /// Hands a decoded `event` over to the sink
+    fn emit(&self, event: InputEvent) {
+        match self.sink {
+            InputSink::RingBuffer(ringbuffer) => {
+                ringbuffer.write(&[event]).unwrap();
+            }
+            InputSink::Channel(ref sender) => {
+                if sender.send(event).is_err() {
+                    debug!("Dropping an input event, its receiver is gone");
+                }
+            }
+        }
+    }
 }
 
 impl<'a> EvdevHandler for UnifiedInputHandler<'a> {
@@ -4,8 +4,6 @@ use input::{InputEvent, UnifiedInputHandler};
 
 use evdev::raw::input_event;
 
-use rb::RbProducer;
-
 const MT_HSCALAR: f32 = (DISPLAYWIDTH as f32) / (MTWIDTH as f32);
 const MT_VSCALAR: f32 = (DISPLAYHEIGHT as f32) / (MTHEIGHT as f32);
 
@@ -69,9 +67,7 @@ impl<'a> UnifiedInputHandler<'a> {
                             x,
                         };
 
-                        self.ringbuffer
-                            .write(&[InputEvent::MultitouchEvent { event }])
-                            .unwrap();
+                        self.emit(InputEvent::MultitouchEvent { event });
                     }
                     52 | 48 | 58 => debug!(
                         "unknown_absolute_touch_event(code={0}, value={1})",
@@ -13,12 +13,16 @@ extern crate libc;
 extern crate mmap;
 extern crate rusttype;
 
+pub extern crate cgmath;
 pub extern crate epoll;
 pub extern crate evdev;
 pub extern crate image;
 pub extern crate line_drawing;
 pub extern crate rb;
 
+/// Contains `Error`, returned by the fallible functions of the crate
+pub mod error;
+
 /// One of the core components, allowing output and refresh of the EInk display
 pub mod framebuffer;
 
@@ -28,6 +32,9 @@ pub mod input;
 /// Simple battery and charging status provider
 pub mod battery;
 
+/// Frontlight brightness control for the models that have one
+pub mod frontlight;
+
 /// Contains the `ApplicationContext`, which is a general framework that can be used to either build
 /// your application or design your I/O code after. It uses rudimentary UI elements and adds them
 /// to a scene after wrapping them in `UIElementWrapper`. None of these are mandatory to be used.
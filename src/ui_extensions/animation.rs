@@ -0,0 +1,88 @@
+use framebuffer::common::*;
+use framebuffer::refresh::PartialRefreshMode;
+use framebuffer::FramebufferRefresh;
+
+use appctx;
+
+/// What an animation did during a single frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AnimationStep {
+    /// Area drawn to during the frame, if any
+    pub dirty: Option<mxcfb_rect>,
+    /// Once true, the animation is dropped after this frame
+    pub done: bool,
+}
+
+/// Draws the next frame of an animation. Animations keep track of their own progress, e.g.
+/// by capturing an `Instant` or a frame counter.
+pub type Animation = Box<dyn FnMut(&mut appctx::ApplicationContext) -> AnimationStep + Send>;
+
+/// Drives a set of animations from a single frame loop. Each `tick` draws the next frame of
+/// every animation and refreshes the union of what they drew at once, instead of every
+/// animation refreshing on its own.
+pub struct Animator {
+    pub waveform: waveform_mode,
+    animations: Vec<Animation>,
+}
+
+impl Animator {
+    /// Creates an animator refreshing with `WAVEFORM_MODE_DU`, fast enough for most
+    /// animations
+    pub fn new() -> Animator {
+        Animator {
+            waveform: waveform_mode::WAVEFORM_MODE_DU,
+            animations: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, animation: Animation) {
+        self.animations.push(animation);
+    }
+
+    /// Number of animations that aren't done yet
+    pub fn len(&self) -> usize {
+        self.animations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.animations.is_empty()
+    }
+
+    /// Advances every animation by one frame, drops the ones that are done and refreshes
+    /// the area they drew to. Meant to be called from a timer at the desired frame rate.
+    /// Returns the refreshed area, `None` if nothing was drawn.
+    pub fn tick(&mut self, app: &mut appctx::ApplicationContext) -> Option<mxcfb_rect> {
+        let mut dirty: Option<mxcfb_rect> = None;
+        let mut running = Vec::with_capacity(self.animations.len());
+        for mut animation in self.animations.drain(..) {
+            let step = animation(app);
+            if let Some(rect) = step.dirty {
+                dirty = Some(dirty.map_or(rect, |d| d.union(&rect)));
+            }
+            if !step.done {
+                running.push(animation);
+            }
+        }
+        self.animations = running;
+
+        if let Some(ref area) = dirty {
+            if let Err(e) = app.get_framebuffer_ref().partial_refresh(
+                area,
+                PartialRefreshMode::Async,
+                self.waveform,
+                display_temp::TEMP_USE_REMARKABLE_DRAW,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+            ) {
+                warn!("Failed to refresh an animation frame: {0}", e);
+            }
+        }
+        dirty
+    }
+}
+
+impl Default for Animator {
+    fn default() -> Animator {
+        Animator::new()
+    }
+}
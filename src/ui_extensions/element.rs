@@ -1,5 +1,6 @@
 use std;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use std::hash::{Hash, Hasher};
 
 use image;
@@ -8,16 +9,26 @@ use framebuffer::common;
 use framebuffer::FramebufferRefresh;
 use framebuffer::refresh::PartialRefreshMode;
 use framebuffer::FramebufferDraw;
+use framebuffer::draw::{transformed_size, FontId};
+use framebuffer::offscreen::Rotation;
 use framebuffer::common::{color, mxcfb_rect};
 
 use appctx;
 
 pub type ActiveRegionFunction = fn(&mut appctx::ApplicationContext, Arc<RwLock<UIElementWrapper>>);
 
+/// Called by `UIElementWrapper::draw` right after the built-in drawing of the element with the
+/// rect it was drawn to, allowing the element to be decorated without new `UIElement` variants
+pub type DrawCallback = Arc<dyn Fn(&mut appctx::ApplicationContext, mxcfb_rect) + Send + Sync>;
+
 #[derive(Clone)]
 pub struct ActiveRegionHandler {
     pub handler: ActiveRegionFunction,
     pub element: Arc<RwLock<UIElementWrapper>>,
+    /// Taps outside of these bounds are ignored even if they land on the region
+    pub clip: Option<mxcfb_rect>,
+    /// `UIElementWrapper::z` of the element, regions with a higher one catch taps first
+    pub z: i32,
 }
 
 impl<'a> std::fmt::Debug for ActiveRegionHandler {
@@ -26,29 +37,126 @@ impl<'a> std::fmt::Debug for ActiveRegionHandler {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub enum UIConstraintRefresh {
     NoRefresh,
+    #[default]
     Refresh,
     RefreshAndWait,
+    /// Same as `Refresh`, or `RefreshAndWait` when `wait` is set, with the given parameters
+    /// instead of the defaults, e.g. `WAVEFORM_MODE_GC16` for photos. The parameters left
+    /// `None` keep their default.
+    RefreshWith {
+        waveform_mode: Option<common::waveform_mode>,
+        temperature: Option<common::display_temp>,
+        dither_mode: Option<common::dither_mode>,
+        wait: bool,
+    },
 }
 
-impl Default for UIConstraintRefresh {
-    fn default() -> UIConstraintRefresh {
-        UIConstraintRefresh::Refresh
+impl UIConstraintRefresh {
+    /// Waveform, temperature and dithering to refresh with along with whether to wait for
+    /// the refresh, `None` for `NoRefresh`. Unless overridden, `default_waveform` is used at
+    /// `TEMP_USE_REMARKABLE_DRAW` without dithering.
+    pub fn parameters(
+        &self,
+        default_waveform: common::waveform_mode,
+    ) -> Option<(
+        common::waveform_mode,
+        common::display_temp,
+        common::dither_mode,
+        bool,
+    )> {
+        let temperature = common::display_temp::TEMP_USE_REMARKABLE_DRAW;
+        let dither = common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH;
+        match *self {
+            UIConstraintRefresh::NoRefresh => None,
+            UIConstraintRefresh::Refresh => Some((default_waveform, temperature, dither, false)),
+            UIConstraintRefresh::RefreshAndWait => {
+                Some((default_waveform, temperature, dither, true))
+            }
+            UIConstraintRefresh::RefreshWith {
+                waveform_mode,
+                temperature: temp,
+                dither_mode,
+                wait,
+            } => Some((
+                waveform_mode.unwrap_or(default_waveform),
+                temp.unwrap_or(temperature),
+                dither_mode.unwrap_or(dither),
+                wait,
+            )),
+        }
     }
 }
 
-#[derive(Clone, Default)]
+/// Screen edge (or the center of the screen) that the position of a `UIElementWrapper`
+/// is resolved against at draw time. With an anchor set, `x` and `y` become offsets
+/// pointing inwards from that edge, so `TopRight` with `x: 20` keeps the right edge of
+/// the element 20px away from the right edge of the screen on any resolution.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UIAnchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+#[derive(Clone)]
 pub struct UIElementWrapper {
+    /// Name the element is registered under with `ApplicationContext::add_element`, which
+    /// unlike its position identifies it for as long as it is registered
+    pub name: Option<String>,
     pub y: usize,
     pub x: usize,
+    /// When `None` (the default), `x` and `y` are absolute coordinates
+    pub anchor: Option<UIAnchor>,
     pub refresh: UIConstraintRefresh,
     pub last_drawn_rect: Option<common::mxcfb_rect>,
+    /// What a `UIElement::ProgressBar` looked like when it was last drawn, telling whether
+    /// more than its `fraction` changed since
+    pub last_drawn_bar: Option<ProgressBar>,
     pub onclick: Option<ActiveRegionFunction>,
+    /// When `onclick` was last called for a tap, used to debounce repeated taps
+    pub last_triggered: Option<Instant>,
+    /// Bounds of the panel or viewport the element is shown in. When set, only the part of
+    /// the element within them responds to taps.
+    pub clip: Option<common::mxcfb_rect>,
+    pub on_draw: Option<DrawCallback>,
+    /// Elements sharing a group are refreshed together by `ApplicationContext::refresh_group`
+    pub group_id: Option<u32>,
+    /// A hidden element is cleared from the screen and stops responding to taps, without
+    /// losing what it shows
+    pub visible: bool,
+    /// Elements are drawn in ascending `z`, so those with a higher one end up on top and
+    /// catch the taps where they overlap others. Defaults to 0.
+    pub z: i32,
     pub inner: UIElement,
 }
 
+impl Default for UIElementWrapper {
+    fn default() -> UIElementWrapper {
+        UIElementWrapper {
+            name: None,
+            y: 0,
+            x: 0,
+            anchor: None,
+            refresh: UIConstraintRefresh::default(),
+            last_drawn_rect: None,
+            last_drawn_bar: None,
+            onclick: None,
+            last_triggered: None,
+            clip: None,
+            on_draw: None,
+            group_id: None,
+            visible: true,
+            z: 0,
+            inner: UIElement::default(),
+        }
+    }
+}
+
 impl Hash for UIElementWrapper {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.x.hash(state);
@@ -64,30 +172,301 @@ impl PartialEq for UIElementWrapper {
 
 impl Eq for UIElementWrapper {}
 
-#[derive(Clone)]
+/// A bar of `height` by `width` filled with `fill` from the left in proportion to `fraction`,
+/// over `track` and within `border_px` thick edges drawn with `fill`, see
+/// `ApplicationContext::display_progress_bar`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProgressBar {
+    pub height: usize,
+    pub width: usize,
+    pub fraction: f32,
+    pub fill: color,
+    pub track: color,
+    pub border_px: u32,
+}
+
+#[derive(Clone, Default)]
 pub enum UIElement {
     Text {
         text: String,
         scale: usize,
         foreground: color,
+        /// When set, the text is word-wrapped to lines no wider than this
+        max_width: Option<usize>,
+        /// Font loaded with `ApplicationContext::load_font`, the default font when `None`
+        font: Option<FontId>,
     },
+    /// `img` scaled by `scale` and then turned by `rotation`, see `transform_image`
     Image {
         img: image::DynamicImage,
+        scale: f32,
+        rotation: Rotation,
+    },
+    /// A box of `height` by `width`, either filled with `color` or outlined with
+    /// `border_px` thick edges drawn inwards
+    Rectangle {
+        width: usize,
+        height: usize,
+        border_px: u32,
+        color: color,
+        filled: bool,
     },
+    /// See `ProgressBar`. When only `fraction` changes between draws, only the changed span
+    /// is refreshed.
+    ProgressBar {
+        height: usize,
+        width: usize,
+        fraction: f32,
+        fill: color,
+        track: color,
+        border_px: u32,
+    },
+    /// `label` centered in a bordered box sized to fit it with `padding` pixels around it.
+    /// When tapped, the button is briefly shown with its colors swapped before `onclick`
+    /// runs. A non-zero `corner_radius` rounds off its corners.
+    Button {
+        label: String,
+        scale: usize,
+        padding: usize,
+        foreground: color,
+        background: color,
+        corner_radius: u32,
+    },
+    /// A box of `height_px` by `width_px` showing the `lines` that fall within it once
+    /// scrolled down by `scroll` pixels, e.g. the tail of a log. Lines cut by its edges are
+    /// clipped and the whole box is redrawn every time, see
+    /// `ApplicationContext::display_text_viewport`.
+    TextViewport {
+        lines: Vec<String>,
+        scale: usize,
+        height_px: usize,
+        width_px: usize,
+        scroll: usize,
+        foreground: color,
+    },
+    /// Lays out the elements named in `children` left to right from its own position,
+    /// wrapping to a new row before exceeding `width`, with `spacing` pixels between them.
+    /// Draws nothing itself, see `ApplicationContext::reflow_container`.
+    Container {
+        children: Vec<String>,
+        width: usize,
+        spacing: usize,
+    },
+    #[default]
     Unspecified,
 }
 
 impl UIElementWrapper {
+    /// Best known size of the element as `(height, width)`. The dimensions of a text
+    /// aren't known before it is drawn, so for those the last drawn size is used.
+    fn size_hint(&self) -> (usize, usize) {
+        match self.inner {
+            UIElement::Image {
+                ref img,
+                scale,
+                rotation,
+            } => {
+                let (height, width) = transformed_size(img, scale, rotation);
+                (height as usize, width as usize)
+            }
+            UIElement::Rectangle { width, height, .. }
+            | UIElement::ProgressBar { width, height, .. } => (height, width),
+            UIElement::TextViewport {
+                height_px,
+                width_px,
+                ..
+            } => (height_px, width_px),
+            _ => match self.last_drawn_rect {
+                Some(rect) => (rect.height as usize, rect.width as usize),
+                None => (0, 0),
+            },
+        }
+    }
+
+    /// Rect the element would cover if it was drawn now
+    pub fn predicted_rect(&self, app: &mut appctx::ApplicationContext) -> mxcfb_rect {
+        let framebuffer = app.get_framebuffer_ref();
+        let (y, x) = self.resolve_position(
+            framebuffer.var_screen_info.yres as usize,
+            framebuffer.var_screen_info.xres as usize,
+        );
+        match self.inner {
+            UIElement::Text {
+                ref text,
+                scale,
+                max_width: None,
+                font,
+                ..
+            } => framebuffer.measure_text_font(y, x, text, scale, font),
+            UIElement::Text {
+                ref text,
+                scale,
+                max_width: Some(max_width),
+                font,
+                ..
+            } => {
+                let line_height = framebuffer.line_height_font(scale, font);
+                framebuffer
+                    .wrap_text_font(text, scale, max_width, font)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        framebuffer.measure_text_font(y + i * line_height, x, line, scale, font)
+                    })
+                    .filter(|r| r.width > 0 && r.height > 0)
+                    .fold(None, |acc: Option<mxcfb_rect>, r| {
+                        Some(acc.map_or(r, |a| a.union(&r)))
+                    })
+                    .unwrap_or_else(mxcfb_rect::invalid)
+            }
+            UIElement::Image {
+                ref img,
+                scale,
+                rotation,
+            } => {
+                let (height, width) = transformed_size(img, scale, rotation);
+                mxcfb_rect {
+                    top: y as u32,
+                    left: x as u32,
+                    height,
+                    width,
+                }
+            }
+            UIElement::Rectangle { width, height, .. }
+            | UIElement::ProgressBar { width, height, .. }
+            | UIElement::TextViewport {
+                width_px: width,
+                height_px: height,
+                ..
+            } => mxcfb_rect {
+                top: y as u32,
+                left: x as u32,
+                height: height as u32,
+                width: width as u32,
+            },
+            UIElement::Button {
+                ref label,
+                scale,
+                padding,
+                ..
+            } => app.button_rect(y, x, label, scale, padding),
+            _ => self.last_drawn_rect.unwrap_or_default(),
+        }
+    }
+
+    /// Resolves the `(y, x)` the element will be drawn at on a screen of `yres` by `xres`
+    pub fn resolve_position(&self, yres: usize, xres: usize) -> (usize, usize) {
+        let (height, width) = self.size_hint();
+        let right = xres.saturating_sub(width + self.x);
+        let bottom = yres.saturating_sub(height + self.y);
+        match self.anchor {
+            None | Some(UIAnchor::TopLeft) => (self.y, self.x),
+            Some(UIAnchor::TopRight) => (self.y, right),
+            Some(UIAnchor::BottomLeft) => (bottom, self.x),
+            Some(UIAnchor::BottomRight) => (bottom, right),
+            Some(UIAnchor::Center) => (
+                yres.saturating_sub(height) / 2 + self.y,
+                xres.saturating_sub(width) / 2 + self.x,
+            ),
+        }
+    }
+
+    /// Clears the area the element was last drawn to and removes its active region, which
+    /// `handler` identifies
+    fn hide(
+        &mut self,
+        app: &mut appctx::ApplicationContext,
+        handler: Option<ActiveRegionHandler>,
+        refresh: UIConstraintRefresh,
+    ) {
+        let rect = match self.last_drawn_rect.take() {
+            Some(rect) => rect,
+            None => return,
+        };
+        app.get_framebuffer_ref().fill_rect(
+            rect.top as usize,
+            rect.left as usize,
+            rect.height as usize,
+            rect.width as usize,
+            color::WHITE,
+        );
+        if let Some(ref h) = handler {
+            app.remove_active_region_of(rect.top as u16, rect.left as u16, &h.element);
+        }
+        app.refresh_drawn_area(rect, refresh);
+    }
+
+    /// Flashes a `UIElement::Button` with its colors swapped using `WAVEFORM_MODE_DU`, and
+    /// then draws it normally again. Other elements are left untouched.
+    pub fn press_feedback(&self, app: &mut appctx::ApplicationContext) {
+        let rect = match self.last_drawn_rect {
+            Some(rect) => rect,
+            None => return,
+        };
+        if let UIElement::Button {
+            ref label,
+            scale,
+            padding,
+            foreground,
+            background,
+            corner_radius,
+        } = self.inner
+        {
+            let (y, x) = (rect.top as usize, rect.left as usize);
+            let flashes = [
+                (background, foreground, PartialRefreshMode::Wait),
+                (foreground, background, PartialRefreshMode::Async),
+            ];
+            for &(fg, bg, mode) in flashes.iter() {
+                let drawn = app.display_button(
+                    y,
+                    x,
+                    label,
+                    scale,
+                    padding,
+                    fg,
+                    bg,
+                    corner_radius,
+                    UIConstraintRefresh::NoRefresh,
+                );
+                if let Err(e) = app.get_framebuffer_ref().partial_refresh(
+                    &drawn,
+                    mode,
+                    common::waveform_mode::WAVEFORM_MODE_DU,
+                    common::display_temp::TEMP_USE_REMARKABLE_DRAW,
+                    common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                    0,
+                ) {
+                    warn!("Failed to refresh a pressed button: {0}", e);
+                }
+            }
+        }
+    }
+
     pub fn draw(
         &mut self,
         app: &mut appctx::ApplicationContext,
         handler: Option<ActiveRegionHandler>,
     ) {
-        let (x, y) = (self.x, self.y);
         let refresh = self.refresh.clone();
+        if !self.visible {
+            return self.hide(app, handler, refresh);
+        }
         let framebuffer = app.get_framebuffer_ref();
+        let (y, x) = self.resolve_position(
+            framebuffer.var_screen_info.yres as usize,
+            framebuffer.var_screen_info.xres as usize,
+        );
 
+        let predicted = self.predicted_rect(app);
+        // A progress bar redrawn in place overwrites all of its previous pixels, so it is
+        // left on screen to find out which of them change
+        let in_place = match self.inner {
+            UIElement::ProgressBar { .. } => self.last_drawn_rect == Some(predicted),
+            _ => false,
+        };
         let old_filled_rect = match self.last_drawn_rect {
+            Some(rect) if in_place => rect,
             Some(rect) => {
                 // Clear the background on the last occupied region
                 framebuffer.fill_rect(
@@ -98,20 +477,26 @@ impl UIElementWrapper {
                     color::WHITE,
                 );
 
-                // We have filled the old_filled_rect, now we need to also refresh that but if
-                // only if it isn't at the same spot. Otherwise we will be refreshing it for no
-                // reason and showing a blank frame. There is of course still a caveat since we don't
-                // know the dimensions of a drawn text before it is actually drawn.
-                // TODO: Take care of the point above ^
-                if rect.top != y as u32 && rect.left != x as u32 {
-                    framebuffer.partial_refresh(
-                        &rect,
+                // Only the parts of the cleared area that the element won't cover again need a
+                // refresh of their own, e.g. the trailing sliver when its text got shorter. The
+                // refresh of the redrawn element takes care of the rest without a blank frame.
+                // Also refreshed for elements that aren't, using the defaults then.
+                let du = common::waveform_mode::WAVEFORM_MODE_DU;
+                let (waveform, temperature, dither, _) = match refresh.parameters(du) {
+                    Some(parameters) => parameters,
+                    None => UIConstraintRefresh::Refresh.parameters(du).unwrap(),
+                };
+                for uncovered in rect.difference(&predicted) {
+                    if let Err(e) = framebuffer.partial_refresh(
+                        &uncovered,
                         PartialRefreshMode::Wait,
-                        common::waveform_mode::WAVEFORM_MODE_DU,
-                        common::display_temp::TEMP_USE_REMARKABLE_DRAW,
-                        common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                        waveform,
+                        temperature,
+                        dither,
                         0,
-                    );
+                    ) {
+                        warn!("Failed to refresh the previous area of an element: {0}", e);
+                    }
                 }
 
                 rect
@@ -125,29 +510,112 @@ impl UIElementWrapper {
                 ref text,
                 scale,
                 foreground,
-            } => app.display_text(y, x, foreground, scale, text.to_string(), refresh),
-            UIElement::Image { ref img } => app.display_image(&img, y, x, refresh),
-            UIElement::Unspecified => return,
+                max_width,
+                font,
+            } => match max_width {
+                Some(max_width) => app.display_text_wrapped(
+                    y, x, foreground, scale, text, max_width, font, refresh,
+                ),
+                None => app.display_text_font(y, x, foreground, scale, text, font, refresh),
+            },
+            UIElement::Image {
+                ref img,
+                scale,
+                rotation,
+            } => match scale == 1.0 && rotation == Rotation::None {
+                true => app.display_image(&img, y, x, refresh),
+                false => app.display_image_transformed(&img, y, x, scale, rotation, refresh),
+            },
+            UIElement::Rectangle {
+                width,
+                height,
+                border_px,
+                color: c,
+                filled,
+            } => app.display_rect(y, x, height, width, border_px, c, filled, refresh),
+            UIElement::ProgressBar {
+                height,
+                width,
+                fraction,
+                fill,
+                track,
+                border_px,
+            } => {
+                let bar = ProgressBar {
+                    height,
+                    width,
+                    fraction,
+                    fill,
+                    track,
+                    border_px,
+                };
+                let previous = match in_place {
+                    true => self.last_drawn_bar.take(),
+                    false => None,
+                };
+                self.last_drawn_bar = Some(bar);
+                app.display_progress_bar(y, x, &bar, previous.as_ref(), refresh)
+            }
+            UIElement::Button {
+                ref label,
+                scale,
+                padding,
+                foreground,
+                background,
+                corner_radius,
+            } => app.display_button(
+                y,
+                x,
+                label,
+                scale,
+                padding,
+                foreground,
+                background,
+                corner_radius,
+                refresh,
+            ),
+            UIElement::TextViewport {
+                ref lines,
+                scale,
+                height_px,
+                width_px,
+                scroll,
+                foreground,
+            } => app.display_text_viewport(
+                y, x, height_px, width_px, lines, scale, scroll, foreground, refresh,
+            ),
+            UIElement::Container { .. } | UIElement::Unspecified => return,
         };
 
-        // If no changes, no need to change the active region
-        if old_filled_rect != rect {
+        if let Some(ref on_draw) = self.on_draw {
+            on_draw(app, rect);
+        }
+
+        // If no changes, no need to change the active region. The clip bounds of an element
+        // are likely to change between draws without it moving (scrolling), so those always
+        // get their active region updated.
+        if old_filled_rect != rect || self.clip.is_some() {
             if let Some(ref h) = handler {
+                // Only the region of this element, others may be stacked on the same point,
+                // e.g. a dialog over the screen it was opened from
                 if old_filled_rect != mxcfb_rect::invalid() {
-                    app.remove_active_region_at_point(
+                    app.remove_active_region_of(
                         old_filled_rect.top as u16,
                         old_filled_rect.left as u16,
+                        &h.element,
                     );
                 }
 
-                if app.find_active_region(y as u16, x as u16).is_none() {
-                    app.create_active_region(
+                if !app.has_active_region_of(rect.top as u16, rect.left as u16, &h.element) {
+                    app.create_layered_active_region(
                         rect.top as u16,
                         rect.left as u16,
                         rect.height as u16,
                         rect.width as u16,
                         h.handler,
                         Arc::clone(&h.element),
+                        self.clip,
+                        self.z,
                     );
                 }
             }
@@ -158,9 +626,3 @@ impl UIElementWrapper {
         self.last_drawn_rect = Some(rect);
     }
 }
-
-impl Default for UIElement {
-    fn default() -> UIElement {
-        UIElement::Unspecified
-    }
-}
@@ -47,7 +47,7 @@ pub fn lua_refresh(
                 height: nheight as u32,
                 width: nwidth as u32,
             };
-            match bdeep {
+            let result = match bdeep {
                 false => framebuffer.partial_refresh(
                     &rect,
                     match bwait {
@@ -71,6 +71,9 @@ pub fn lua_refresh(
                     0,
                 ),
             };
+            if let Err(e) = result {
+                warn!("Failed to refresh the region requested from lua: {0}", e);
+            }
         }
         _ => {}
     };
@@ -120,11 +123,13 @@ pub fn lua_set_pixel(y: hlua::AnyLuaValue, x: hlua::AnyLuaValue, color: hlua::An
 pub fn lua_clear() {
     let framebuffer = get_current_framebuffer!();
     framebuffer.clear();
-    framebuffer.full_refresh(
+    if let Err(e) = framebuffer.full_refresh(
         waveform_mode::WAVEFORM_MODE_INIT,
         display_temp::TEMP_USE_AMBIENT,
         dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
         0,
         true,
-    );
+    ) {
+        warn!("Failed to refresh the cleared screen: {0}", e);
+    }
 }
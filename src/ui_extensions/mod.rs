@@ -6,3 +6,13 @@ pub mod luaext;
 /// `ApplicationContext` and `ui_extensions` and choose to interact with the `framebuffer`
 /// and `input` devices directly.
 pub mod element;
+
+/// Contains `TextLog`, a terminal-style view that scrolls its content up as lines are appended
+pub mod textlog;
+
+/// Contains `OverlayManager`, which keeps track of the backgrounds of stacked overlays so that
+/// they can be removed in any order
+pub mod overlay;
+
+/// Contains `Animator`, a frame loop coalescing the refreshes of several animations
+pub mod animation;
@@ -0,0 +1,152 @@
+use error::Error;
+use framebuffer::common::*;
+use framebuffer::refresh::PartialRefreshMode;
+use framebuffer::FramebufferIO;
+use framebuffer::FramebufferRefresh;
+
+use appctx;
+
+/// Identifies an overlay registered with an `OverlayManager`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OverlayId(usize);
+
+struct Overlay {
+    id: OverlayId,
+    rect: mxcfb_rect,
+    /// Contents of `rect` under the overlay, in the native pixel format
+    background: Vec<u8>,
+}
+
+/// Keeps the backgrounds of a stack of overlays such as nested popups.
+///
+/// Naively restoring the background of an overlay that isn't the topmost one paints over the
+/// overlays shown above it and leaves them with a saved background that still contains the
+/// removed overlay, which then reappears once they are removed too. Instead, `remove` only
+/// restores the parts of the screen that no remaining overlay covers, and hands the rest of
+/// the background over to the overlays above.
+#[derive(Default)]
+pub struct OverlayManager {
+    /// Bottom to top
+    overlays: Vec<Overlay>,
+    next_id: usize,
+}
+
+impl OverlayManager {
+    pub fn new() -> OverlayManager {
+        OverlayManager {
+            overlays: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Saves what is currently under `rect` and registers an overlay on top of the others
+    /// there. The overlay itself is to be drawn by the caller afterwards.
+    pub fn push(
+        &mut self,
+        app: &mut appctx::ApplicationContext,
+        rect: mxcfb_rect,
+    ) -> Result<OverlayId, Error> {
+        let background = app.get_framebuffer_ref().dump_region(rect)?;
+        let id = OverlayId(self.next_id);
+        self.next_id += 1;
+        self.overlays.push(Overlay {
+            id,
+            rect,
+            background,
+        });
+        Ok(id)
+    }
+
+    /// Rects of the overlays still shown, bottom to top
+    pub fn rects(&self) -> Vec<mxcfb_rect> {
+        self.overlays.iter().map(|o| o.rect).collect()
+    }
+
+    /// Removes the overlay `id`, restoring the parts of its background that aren't covered
+    /// by the overlays above it and refreshing them. Returns the refreshed area, `None` if
+    /// `id` is unknown or nothing on screen had to change.
+    pub fn remove(
+        &mut self,
+        app: &mut appctx::ApplicationContext,
+        id: OverlayId,
+    ) -> Option<mxcfb_rect> {
+        let index = match self.overlays.iter().position(|o| o.id == id) {
+            Some(index) => index,
+            None => return None,
+        };
+        let removed = self.overlays.remove(index);
+        let framebuffer = app.get_framebuffer_ref();
+        let bytespp = (framebuffer.var_screen_info.bits_per_pixel / 8) as usize;
+        let rect = removed.rect;
+        let row_len = rect.width as usize * bytespp;
+
+        // Overlays that were above the removed one and overlap with it
+        let above: Vec<usize> = (index..self.overlays.len())
+            .filter(|&i| self.overlays[i].rect.intersection(&rect).is_some())
+            .collect();
+
+        let mut restored: Option<mxcfb_rect> = None;
+        for row in 0..rect.height {
+            let y = rect.top + row;
+            let line = &removed.background[row as usize * row_len..(row as usize + 1) * row_len];
+            let mut col = 0;
+            while col < rect.width {
+                // Find the run of pixels starting at `col` that are either all uncovered or
+                // all covered by the same lowest overlay above
+                let owner = |x: u32| {
+                    above.iter().cloned().find(|&i| {
+                        let mut r = self.overlays[i].rect;
+                        r.contains_point(y, x)
+                    })
+                };
+                let run_owner = owner(rect.left + col);
+                let mut end = col + 1;
+                while end < rect.width && owner(rect.left + end) == run_owner {
+                    end += 1;
+                }
+                let bytes = &line[col as usize * bytespp..end as usize * bytespp];
+
+                match run_owner {
+                    // What was under the removed overlay is now under the one above it
+                    Some(i) => {
+                        let over = &mut self.overlays[i];
+                        let over_row_len = over.rect.width as usize * bytespp;
+                        let offset = (y - over.rect.top) as usize * over_row_len
+                            + (rect.left + col - over.rect.left) as usize * bytespp;
+                        over.background[offset..offset + bytes.len()].copy_from_slice(bytes);
+                    }
+                    None => {
+                        let run = mxcfb_rect {
+                            top: y,
+                            left: rect.left + col,
+                            height: 1,
+                            width: end - col,
+                        };
+                        if let Err(e) = framebuffer.restore_region(run, bytes) {
+                            warn!("Unable to restore the background of an overlay: {0}", e);
+                        }
+                        restored = Some(match restored {
+                            Some(r) => r.union(&run),
+                            None => run,
+                        });
+                    }
+                }
+                col = end;
+            }
+        }
+
+        if let Some(ref area) = restored {
+            if let Err(e) = framebuffer.partial_refresh(
+                area,
+                PartialRefreshMode::Async,
+                waveform_mode::WAVEFORM_MODE_GC16_FAST,
+                display_temp::TEMP_USE_REMARKABLE_DRAW,
+                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+                0,
+            ) {
+                warn!("Failed to refresh the area of a removed overlay: {0}", e);
+            }
+        }
+        restored
+    }
+}
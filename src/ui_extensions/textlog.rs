@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use framebuffer::common::*;
+use framebuffer::refresh::PartialRefreshMode;
+use framebuffer::FramebufferDraw;
+use framebuffer::FramebufferIO;
+use framebuffer::FramebufferRefresh;
+
+use appctx;
+
+/// A log view occupying `rect` that appends lines at the bottom. Once the view is full,
+/// the existing content is moved up by one line with `copy_rect` so that only the new line
+/// needs to be rendered.
+pub struct TextLog {
+    pub rect: mxcfb_rect,
+    pub scale: usize,
+    pub foreground: color,
+    /// Maximum number of lines retained for `redraw`, the oldest ones are dropped first
+    pub max_lines: usize,
+    lines: VecDeque<String>,
+    /// Number of lines currently shown, until the view fills up
+    shown: usize,
+}
+
+impl TextLog {
+    pub fn new(rect: mxcfb_rect, scale: usize, foreground: color, max_lines: usize) -> TextLog {
+        TextLog {
+            rect,
+            scale,
+            foreground,
+            max_lines,
+            lines: VecDeque::new(),
+            shown: 0,
+        }
+    }
+
+    /// Lines retained by the log, oldest first
+    pub fn lines(&self) -> &VecDeque<String> {
+        &self.lines
+    }
+
+    fn line_height(&self) -> usize {
+        self.scale + self.scale / 4
+    }
+
+    /// Number of lines that fit in `rect`
+    pub fn capacity(&self) -> usize {
+        self.rect.height as usize / self.line_height()
+    }
+
+    /// Draws `text` in the `slot`th line of the view, clearing what was there before
+    fn draw_line(
+        &self,
+        app: &mut appctx::ApplicationContext,
+        slot: usize,
+        text: &str,
+    ) -> mxcfb_rect {
+        let framebuffer = app.get_framebuffer_ref();
+        let line = mxcfb_rect {
+            top: self.rect.top + (slot * self.line_height()) as u32,
+            left: self.rect.left,
+            height: self.line_height() as u32,
+            width: self.rect.width,
+        };
+        framebuffer.fill_rect(
+            line.top as usize,
+            line.left as usize,
+            line.height as usize,
+            line.width as usize,
+            color::WHITE,
+        );
+        framebuffer.draw_text(
+            line.top as usize + self.scale,
+            line.left as usize,
+            text.to_owned(),
+            self.scale,
+            self.foreground,
+        );
+        line
+    }
+
+    /// Appends `text` at the bottom of the view, scrolling the older lines up if the view is
+    /// full, and refreshes only the area that changed. Returns that area.
+    pub fn push_line(&mut self, app: &mut appctx::ApplicationContext, text: &str) -> mxcfb_rect {
+        self.lines.push_back(text.to_owned());
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+        }
+
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return mxcfb_rect::invalid();
+        }
+
+        let dirty = if self.shown < capacity {
+            let slot = self.shown;
+            self.shown += 1;
+            self.draw_line(app, slot, text)
+        } else {
+            let line_height = self.line_height() as u32;
+            app.get_framebuffer_ref().copy_rect(
+                mxcfb_rect {
+                    top: self.rect.top + line_height,
+                    left: self.rect.left,
+                    height: (capacity as u32 - 1) * line_height,
+                    width: self.rect.width,
+                },
+                self.rect.top,
+                self.rect.left,
+            );
+            self.draw_line(app, capacity - 1, text);
+            mxcfb_rect {
+                top: self.rect.top,
+                left: self.rect.left,
+                height: capacity as u32 * line_height,
+                width: self.rect.width,
+            }
+        };
+
+        if let Err(e) = app.get_framebuffer_ref().partial_refresh(
+            &dirty,
+            PartialRefreshMode::Async,
+            waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        ) {
+            warn!("Failed to refresh a text log: {0}", e);
+        }
+        dirty
+    }
+
+    /// Redraws the most recent retained lines that fit the view and refreshes the whole view
+    pub fn redraw(&mut self, app: &mut appctx::ApplicationContext) -> mxcfb_rect {
+        let capacity = self.capacity();
+        let visible: Vec<String> = self.lines
+            .iter()
+            .skip(self.lines.len().saturating_sub(capacity))
+            .cloned()
+            .collect();
+
+        app.get_framebuffer_ref().fill_rect(
+            self.rect.top as usize,
+            self.rect.left as usize,
+            self.rect.height as usize,
+            self.rect.width as usize,
+            color::WHITE,
+        );
+        for (slot, text) in visible.iter().enumerate() {
+            self.draw_line(app, slot, text);
+        }
+        self.shown = visible.len();
+
+        if let Err(e) = app.get_framebuffer_ref().partial_refresh(
+            &self.rect,
+            PartialRefreshMode::Async,
+            waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        ) {
+            warn!("Failed to refresh a text log: {0}", e);
+        }
+        self.rect
+    }
+}
@@ -0,0 +1,184 @@
+extern crate libremarkable;
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use libremarkable::appctx::ApplicationContext;
+use libremarkable::framebuffer::common::{color, mxcfb_rect};
+use libremarkable::framebuffer::offscreen::Rotation;
+use libremarkable::framebuffer::{FramebufferDraw, FramebufferIO};
+use libremarkable::image::{DynamicImage, GrayImage};
+use libremarkable::input::gpio::GPIOEvent;
+use libremarkable::input::multitouch::MultitouchEvent;
+use libremarkable::input::wacom::WacomEvent;
+use libremarkable::ui_extensions::element::{UIElement, UIElementWrapper};
+
+fn on_button(_: &mut ApplicationContext, _: GPIOEvent) {}
+fn on_wacom(_: &mut ApplicationContext, _: WacomEvent) {}
+fn on_touch(_: &mut ApplicationContext, _: MultitouchEvent) {}
+fn on_click(_: &mut ApplicationContext, _: Arc<RwLock<UIElementWrapper>>) {}
+
+/// Number of pixels of the framebuffer of `app` that aren't white
+fn non_white_pixels(app: &mut ApplicationContext, yres: usize, xres: usize) -> usize {
+    let framebuffer = app.get_framebuffer_ref();
+    let mut count = 0;
+    for y in 0..yres {
+        for x in 0..xres {
+            if framebuffer.read_pixel(y, x).as_native() != color::WHITE.as_native() {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[test]
+fn test_toast_leaves_nothing_behind() {
+    let (yres, xres) = (400, 600);
+    let mut app = ApplicationContext::in_memory(yres, xres, on_button, on_wacom, on_touch);
+    // Texts of both an odd and an even height
+    for text in &["Saved", "Saved to the cloud, gently"] {
+        app.get_framebuffer_ref().clear();
+        app.show_toast(text, Duration::from_secs(0));
+        assert!(non_white_pixels(&mut app, yres as usize, xres as usize) > 0);
+
+        // All the rows under the pill included
+        assert_eq!(app.update_toasts(), None);
+        assert_eq!(non_white_pixels(&mut app, yres as usize, xres as usize), 0);
+    }
+}
+
+/// A filled rectangle of `height` by `width` at `(y, x)` that responds to taps
+fn clickable_rect(y: usize, x: usize, height: usize, width: usize, z: i32) -> UIElementWrapper {
+    UIElementWrapper {
+        y,
+        x,
+        z,
+        onclick: Some(on_click),
+        inner: UIElement::Rectangle {
+            height,
+            width,
+            border_px: 0,
+            color: color::GRAY(0x80),
+            filled: true,
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_redrawn_element_under_dialog_keeps_one_region() {
+    let mut app = ApplicationContext::in_memory(400, 600, on_button, on_wacom, on_touch);
+    let base = Arc::new(RwLock::new(clickable_rect(100, 100, 50, 50, 0)));
+    let dialog = Arc::new(RwLock::new(clickable_rect(50, 50, 200, 200, 1)));
+    app.add_element("base", Arc::clone(&base));
+    app.add_element("dialog", Arc::clone(&dialog));
+    app.draw_elements();
+    let dialog_rect = mxcfb_rect {
+        top: 50,
+        left: 50,
+        height: 200,
+        width: 200,
+    };
+    let mut moved = mxcfb_rect {
+        top: 100,
+        left: 100,
+        height: 50,
+        width: 50,
+    };
+
+    // Moved, then clipped, each time with its top left corner covered by the dialog
+    base.write().unwrap().x = 110;
+    moved.left = 110;
+    app.draw_element("base");
+    base.write().unwrap().clip = Some(dialog_rect);
+    app.draw_element("base");
+    app.draw_element("base");
+
+    let mut regions = app.active_regions();
+    regions.sort_by_key(|r| r.top);
+    assert_eq!(regions, vec![dialog_rect, moved]);
+    let (found, _) = app.find_active_region(120, 120).unwrap();
+    assert!(Arc::ptr_eq(&found.element, &dialog));
+}
+
+#[test]
+fn test_present_keeps_the_frame_going() {
+    let mut app = ApplicationContext::in_memory(400, 600, on_button, on_wacom, on_touch);
+    app.get_framebuffer_ref().clear();
+    app.present().unwrap();
+    assert!(app.get_framebuffer_ref().submitted_updates().is_empty());
+    for frame in 1..4 {
+        app.get_framebuffer_ref().fill_rect(10, 10 * frame, 10, 10, color::BLACK);
+        app.present().unwrap();
+        assert_eq!(app.get_framebuffer_ref().submitted_updates().len(), frame);
+    }
+}
+
+#[test]
+fn test_image_element_is_transformed() {
+    let mut app = ApplicationContext::in_memory(400, 600, on_button, on_wacom, on_touch);
+    let img = DynamicImage::ImageLuma8(GrayImage::new(20, 10));
+    let element = Arc::new(RwLock::new(UIElementWrapper {
+        y: 30,
+        x: 40,
+        inner: UIElement::Image {
+            img,
+            scale: 2.0,
+            rotation: Rotation::Clockwise90,
+        },
+        ..Default::default()
+    }));
+    app.add_element("icon", Arc::clone(&element));
+    app.draw_element("icon");
+    let drawn = element.read().unwrap().last_drawn_rect;
+    assert_eq!(
+        drawn,
+        Some(mxcfb_rect {
+            top: 30,
+            left: 40,
+            height: 40,
+            width: 20,
+        })
+    );
+}
+
+#[test]
+fn test_progress_bar_refreshes_all_of_a_restyled_bar() {
+    let mut app = ApplicationContext::in_memory(400, 600, on_button, on_wacom, on_touch);
+    let bar = |fraction: f32, track: color| UIElement::ProgressBar {
+        height: 20,
+        width: 200,
+        fraction,
+        fill: color::BLACK,
+        track,
+        border_px: 2,
+    };
+    let element = Arc::new(RwLock::new(UIElementWrapper {
+        y: 100,
+        x: 100,
+        inner: bar(0.25, color::WHITE),
+        ..Default::default()
+    }));
+    app.add_element("progress", Arc::clone(&element));
+    app.draw_element("progress");
+    let whole = element.read().unwrap().last_drawn_rect.unwrap();
+    let last_refreshed = |app: &mut ApplicationContext| {
+        app.get_framebuffer_ref()
+            .submitted_updates()
+            .last()
+            .unwrap()
+            .update_region
+    };
+
+    element.write().unwrap().inner = bar(0.5, color::WHITE);
+    app.draw_element("progress");
+    assert!(last_refreshed(&mut app).width < whole.width / 2);
+
+    // Same value, another track color. Refreshes are grown to at least 32px high.
+    element.write().unwrap().inner = bar(0.5, color::GRAY(0x80));
+    app.draw_element("progress");
+    let refreshed = last_refreshed(&mut app);
+    assert_eq!((refreshed.top, refreshed.left), (whole.top, whole.left));
+    assert_eq!(refreshed.width, whole.width);
+}
@@ -0,0 +1,23 @@
+#![cfg(feature = "draw-stats")]
+extern crate libremarkable;
+
+use libremarkable::framebuffer::common::color;
+use libremarkable::framebuffer::core::Framebuffer;
+use libremarkable::framebuffer::FramebufferDraw;
+
+#[test]
+fn test_take_stats_resets_counters() {
+    let mut fb = Framebuffer::in_memory(100, 200);
+    fb.take_stats();
+    fb.fill_rect(10, 20, 5, 8, color::BLACK);
+    fb.fill_rect(190, 20, 5, 8, color::BLACK);
+    fb.draw_text(50, 10, "stats".to_owned(), 20, color::BLACK);
+
+    let stats = fb.take_stats();
+    assert_eq!(stats.fill_rect.calls, 1);
+    assert_eq!(stats.fill_rect.pixels, 40);
+    assert_eq!(stats.draw_text.calls, 1);
+    assert!(stats.draw_text.pixels > 0);
+    assert_eq!(stats.draw_image.calls, 0);
+    assert_eq!(fb.take_stats().fill_rect.calls, 0);
+}
@@ -0,0 +1,383 @@
+extern crate libremarkable;
+
+use libremarkable::cgmath::Point2;
+use libremarkable::framebuffer::common::{color, display_temp, dither_mode, mxcfb_rect,
+                                         waveform_mode, DISPLAYWIDTH};
+use libremarkable::framebuffer::core::Framebuffer;
+use libremarkable::framebuffer::refresh::PartialRefreshMode;
+use libremarkable::framebuffer::{FramebufferBase, FramebufferDraw, FramebufferIO,
+                                 FramebufferRefresh};
+
+fn refresh(fb: &mut Framebuffer, region: &mxcfb_rect, mode: PartialRefreshMode) -> u32 {
+    fb.partial_refresh(
+        region,
+        mode,
+        waveform_mode::WAVEFORM_MODE_GC16_FAST,
+        display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        0,
+    ).unwrap()
+}
+
+#[test]
+fn test_draw_in_memory() {
+    let mut fb = Framebuffer::in_memory(100, 200);
+    fb.clear();
+    fb.fill_rect(10, 20, 5, 5, color::BLACK);
+    assert_eq!(fb.read_pixel(12, 22).as_native(), color::BLACK.as_native());
+    assert_eq!(fb.read_pixel(50, 100).as_native(), color::WHITE.as_native());
+}
+
+#[test]
+fn test_invert_rect_is_clipped() {
+    let mut fb = Framebuffer::in_memory(100, 200);
+    fb.clear();
+    let region = mxcfb_rect {
+        top: 90,
+        left: 190,
+        height: 20,
+        width: 20,
+    };
+    let inverted = fb.invert_rect(&region);
+    assert_eq!(inverted, mxcfb_rect { top: 90, left: 190, height: 10, width: 10 });
+    assert_eq!(fb.read_pixel(95, 195).as_native(), color::BLACK.as_native());
+    assert_eq!(fb.read_pixel(89, 195).as_native(), color::WHITE.as_native());
+
+    fb.invert_rect(&region);
+    assert_eq!(fb.read_pixel(95, 195).as_native(), color::WHITE.as_native());
+}
+
+#[test]
+fn test_fill_polygon_even_odd() {
+    let mut fb = Framebuffer::in_memory(100, 200);
+    fb.clear();
+    let square = |top: i32, left: i32, size: i32| {
+        vec![
+            Point2 { x: left, y: top },
+            Point2 { x: left + size, y: top },
+            Point2 { x: left + size, y: top + size },
+            Point2 { x: left, y: top + size },
+        ]
+    };
+    // An outer square and an inner one joined into a single outline, leaving a hole
+    let mut outline = square(10, 10, 40);
+    outline.push(Point2 { x: 10, y: 10 });
+    outline.extend(square(20, 20, 20));
+    outline.push(Point2 { x: 20, y: 20 });
+    let rect = fb.fill_polygon(&outline, color::BLACK);
+    assert_eq!(rect, mxcfb_rect { top: 10, left: 10, height: 41, width: 41 });
+    assert_eq!(fb.read_pixel(15, 15).as_native(), color::BLACK.as_native());
+    assert_eq!(fb.read_pixel(30, 30).as_native(), color::WHITE.as_native());
+    assert_eq!(fb.read_pixel(55, 30).as_native(), color::WHITE.as_native());
+
+    let line = [Point2 { x: 0, y: 0 }, Point2 { x: 5, y: 5 }];
+    assert_eq!(fb.fill_polygon(&line, color::BLACK), mxcfb_rect::invalid());
+}
+
+#[test]
+fn test_rows_are_addressed_by_line_length() {
+    // 60px wide rows with 4px of padding at the end of each of them
+    let mut fb = Framebuffer::in_memory(50, 64);
+    fb.var_screen_info.xres = 60;
+    let line_length = fb.fix_screen_info.line_length as isize;
+    fb.clear();
+
+    fb.fill_rect(10, 0, 5, 100, color::BLACK);
+    for y in 10..15 {
+        assert_eq!(fb.read_offset(y * line_length), 0);
+        assert_eq!(fb.read_offset(y * line_length + 59 * 4), 0);
+        // The padding past the last visible pixel is left alone
+        assert_eq!(fb.read_offset(y * line_length + 60 * 4), 0xFF);
+    }
+    assert_eq!(fb.read_offset(15 * line_length), 0xFF);
+
+    let region = mxcfb_rect {
+        top: 20,
+        left: 55,
+        height: 2,
+        width: 5,
+    };
+    let luma: Vec<u8> = (0..10).collect();
+    fb.write_luma(region, &luma);
+    assert_eq!(fb.read_pixel(21, 55).as_native(), color::GRAY(5).as_native());
+    assert_eq!(fb.dump_region(region).unwrap().len(), 10 * 4);
+    assert_eq!(fb.dump_region(region).unwrap()[5 * 4], 5);
+}
+
+#[test]
+fn test_badge_stays_inside_its_rect() {
+    let mut fb = Framebuffer::in_memory(100, 200);
+    for &count in &[7, 42, 1000] {
+        fb.clear();
+        let rect = fb.draw_badge(Point2 { x: 100, y: 50 }, count, color::WHITE, color::BLACK);
+        let inside = |y: u32, x: u32| {
+            y >= rect.top && y < rect.top + rect.height && x >= rect.left
+                && x < rect.left + rect.width
+        };
+        for y in 0..100 {
+            for x in 0..200 {
+                if !inside(y, x) {
+                    let pixel = fb.read_pixel(y as usize, x as usize).as_native();
+                    assert_eq!(pixel, color::WHITE.as_native());
+                }
+            }
+        }
+    }
+
+    let clipped = fb.draw_badge(Point2 { x: 100, y: 99 }, 5, color::WHITE, color::BLACK);
+    assert_eq!(clipped.top + clipped.height, 100);
+}
+
+#[test]
+fn test_partial_refresh_is_recorded() {
+    let mut fb = Framebuffer::in_memory(1404, 1872);
+    let region = mxcfb_rect {
+        top: 100,
+        left: DISPLAYWIDTH as u32 - 10,
+        height: 50,
+        width: 50,
+    };
+    assert_eq!(refresh(&mut fb, &region, PartialRefreshMode::Async), 1);
+    assert_eq!(refresh(&mut fb, &region, PartialRefreshMode::Async), 2);
+
+    let updates = fb.submitted_updates();
+    assert_eq!(updates.len(), 2);
+    assert_eq!(updates[0].update_marker, 1);
+    assert_eq!(updates[1].update_marker, 2);
+    assert_eq!(
+        updates[0].update_region,
+        mxcfb_rect {
+            top: 100,
+            left: DISPLAYWIDTH as u32 - 10,
+            height: 50,
+            width: 10,
+        }
+    );
+}
+
+#[test]
+fn test_waiting_completes_right_away() {
+    let mut fb = Framebuffer::in_memory(1404, 1872);
+    let region = mxcfb_rect {
+        top: 0,
+        left: 0,
+        height: 100,
+        width: 100,
+    };
+    assert_eq!(refresh(&mut fb, &region, PartialRefreshMode::Wait), 0);
+    assert_eq!(fb.submitted_updates().len(), 1);
+}
+
+#[test]
+fn test_auto_temperature_uses_default() {
+    let mut fb = Framebuffer::in_memory(1404, 1872);
+    let region = mxcfb_rect {
+        top: 0,
+        left: 0,
+        height: 100,
+        width: 100,
+    };
+    fb.set_default_temp(display_temp::TEMP_USE_PAPYRUS);
+    for &temp in &[display_temp::TEMP_AUTO, display_temp::TEMP_USE_MAX] {
+        fb.partial_refresh(
+            &region,
+            PartialRefreshMode::Async,
+            waveform_mode::WAVEFORM_MODE_DU,
+            temp,
+            dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            0,
+        ).unwrap();
+    }
+    let updates = fb.submitted_updates();
+    assert_eq!(updates[0].temp, display_temp::TEMP_USE_PAPYRUS as i32);
+    assert_eq!(updates[1].temp, display_temp::TEMP_USE_MAX as i32);
+}
+
+#[test]
+fn test_safe_refresh_waits_on_overlapping_updates() {
+    let mut fb = Framebuffer::in_memory(1404, 1872);
+    let region = |left| mxcfb_rect {
+        top: 0,
+        left,
+        height: 100,
+        width: 100,
+    };
+    refresh(&mut fb, &region(0), PartialRefreshMode::Async);
+    refresh(&mut fb, &region(500), PartialRefreshMode::Async);
+    let marker = fb.partial_refresh_safe(
+        &region(50),
+        PartialRefreshMode::Async,
+        waveform_mode::WAVEFORM_MODE_GC16_FAST,
+        display_temp::TEMP_USE_REMARKABLE_DRAW,
+        dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+        0,
+    ).unwrap();
+    let in_flight: Vec<u32> = fb.in_flight_updates.iter().map(|&(m, _)| m).collect();
+    assert_eq!(in_flight, vec![2, marker]);
+}
+
+#[test]
+fn test_bilevel_classifier_is_shared() {
+    use libremarkable::framebuffer::draw::{is_bilevel, is_bilevel_image};
+    use libremarkable::image::{DynamicImage, GrayImage, Luma};
+
+    // A tenth of intermediate levels is too many, both for sampled levels and images
+    let grays = 10;
+    let levels: Vec<u8> = (0..100).map(|i| if i < grays { 0x80 } else { 0xFF }).collect();
+    assert!(!is_bilevel(levels.iter().cloned()));
+    assert!(is_bilevel(levels[grays..].iter().cloned()));
+
+    let img = GrayImage::from_fn(10, 10, |x, y| Luma([levels[(y * 10 + x) as usize]]));
+    assert!(!is_bilevel_image(&DynamicImage::ImageLuma8(img)));
+}
+
+#[test]
+fn test_clock_wraps_seconds() {
+    let mut fb = Framebuffer::in_memory(300, 300);
+    let center = Point2 { x: 150, y: 150 };
+    fb.clear();
+    let wrapped = fb.draw_clock(center, 100, 3, 59, Some(75), color::BLACK);
+    let wrapped_pixels = fb.dump_region(wrapped).unwrap();
+    fb.clear();
+    let expected = fb.draw_clock(center, 100, 3, 59, Some(15), color::BLACK);
+    assert_eq!(wrapped, expected);
+    assert_eq!(wrapped_pixels, fb.dump_region(expected).unwrap());
+}
+
+#[test]
+fn test_wrap_text() {
+    let fb = Framebuffer::in_memory(100, 100);
+    let size = 30;
+    let width = |line: &str| match fb.measure_glyphs(size, 0, line, size).last() {
+        Some(last) => (last.left + last.width) as usize,
+        None => 0,
+    };
+    let max_width = width("quick brown");
+
+    let lines = fb.wrap_text("the quick brown fox jumps\nover the lazy dog", size, max_width);
+    assert!(lines.len() > 2);
+    assert!(lines.iter().all(|line| width(line) <= max_width));
+    // Words are kept whole and in order, and the newline always breaks the line
+    let words: Vec<&str> = lines.iter().flat_map(|line| line.split(' ')).collect();
+    assert_eq!(words.join(" "), "the quick brown fox jumps over the lazy dog");
+    assert!(lines.iter().any(|line| line.ends_with("jumps")));
+    assert!(lines.iter().any(|line| line.starts_with("over")));
+
+    // A word longer than a line is broken wherever the line is full
+    let long = "abcdefghijklmnopqrstuvwxyz";
+    let broken = fb.wrap_text(long, size, max_width);
+    assert!(broken.len() > 1);
+    assert!(broken.iter().all(|line| width(line) <= max_width));
+    assert_eq!(broken.concat(), long);
+}
+
+#[test]
+fn test_dithering_quantizes_to_panel_levels() {
+    use libremarkable::framebuffer::draw::{dither_luma, DitherAlgorithm};
+
+    // Without dithering every level is rounded to the nearest of the 16, 17 apart
+    let mut ramp: Vec<u8> = (0..=255).collect();
+    dither_luma(&mut ramp, 16, DitherAlgorithm::None);
+    for (level, &out) in ramp.iter().enumerate() {
+        assert_eq!(out as usize, (level + 8) / 17 * 17);
+    }
+
+    // Diffusing the error keeps both the levels and the average brightness
+    for &algorithm in &[DitherAlgorithm::FloydSteinberg, DitherAlgorithm::Atkinson] {
+        let mut flat = vec![0x80u8; 32 * 32];
+        dither_luma(&mut flat, 32, algorithm);
+        assert!(flat.iter().all(|&l| l % 17 == 0));
+        let mean = flat.iter().map(|&l| l as f32).sum::<f32>() / flat.len() as f32;
+        assert!((mean - 128.0).abs() < 4.0, "{:?} averaged {}", algorithm, mean);
+    }
+}
+
+#[test]
+fn test_copy_rect_overlapping() {
+    let mut fb = Framebuffer::in_memory(100, 50);
+    let level = |row: u32| (row * 5) as u8;
+    let src = mxcfb_rect {
+        top: 20,
+        left: 0,
+        height: 40,
+        width: 50,
+    };
+    let fill = |fb: &mut Framebuffer| {
+        let luma: Vec<u8> = (0..src.height)
+            .flat_map(|row| vec![level(row); src.width as usize])
+            .collect();
+        fb.write_luma(src, &luma);
+    };
+    let row_level = |fb: &Framebuffer, y: u32| fb.read_pixel(y as usize, 25).as_native()[0];
+
+    // Moving down and up by less than the height, so that source and destination overlap
+    for &dest_top in &[30u32, 10] {
+        fill(&mut fb);
+        let copied = fb.copy_rect(src, dest_top, 0);
+        assert_eq!(copied, mxcfb_rect { top: dest_top, ..src });
+        for row in 0..src.height {
+            assert_eq!(row_level(&fb, dest_top + row), level(row));
+        }
+    }
+
+    // Clipped at the bottom of the screen
+    fill(&mut fb);
+    let copied = fb.copy_rect(src, 80, 0);
+    assert_eq!(copied.height, 20);
+    for row in 0..20 {
+        assert_eq!(row_level(&fb, 80 + row), level(row));
+    }
+}
+
+#[test]
+fn test_stat_card_too_small_for_its_padding() {
+    let mut fb = Framebuffer::in_memory(100, 100);
+    let rect = mxcfb_rect {
+        top: 0,
+        left: 0,
+        height: 3,
+        width: 40,
+    };
+    let drawn = fb.draw_stat_card(rect, "CPU", "42%", &[0.1, 0.5, 0.3], color::BLACK);
+    assert_eq!(drawn, rect);
+}
+
+#[test]
+fn test_refreshes_off_screen_submit_nothing() {
+    let mut fb = Framebuffer::in_memory(100, 100);
+    let past_right = mxcfb_rect {
+        top: 0,
+        left: DISPLAYWIDTH as u32,
+        height: 50,
+        width: 50,
+    };
+    assert_eq!(refresh(&mut fb, &past_right, PartialRefreshMode::Async), 0);
+
+    fb.set_viewport(Some(mxcfb_rect {
+        top: 0,
+        left: 0,
+        height: 50,
+        width: 50,
+    }));
+    let outside_viewport = mxcfb_rect {
+        top: 60,
+        left: 60,
+        height: 20,
+        width: 20,
+    };
+    assert_eq!(refresh(&mut fb, &outside_viewport, PartialRefreshMode::Async), 0);
+    assert!(fb.submitted_updates().is_empty());
+}
+
+#[test]
+fn test_group_box_at_left_edge_with_zero_scale() {
+    let mut fb = Framebuffer::in_memory(100, 100);
+    let rect = mxcfb_rect {
+        top: 20,
+        left: 0,
+        height: 40,
+        width: 60,
+    };
+    let drawn = fb.draw_group_box(rect, "Title", 0, color::BLACK);
+    assert_eq!(drawn.left, 0);
+}
@@ -0,0 +1,176 @@
+extern crate libremarkable;
+
+use libremarkable::framebuffer::common::{mxcfb_rect, DISPLAYHEIGHT, DISPLAYWIDTH};
+use libremarkable::framebuffer::refresh::clamp_update_region;
+
+#[test]
+fn test_region_at_right_edge() {
+    let region = mxcfb_rect {
+        top: 100,
+        left: DISPLAYWIDTH as u32 - 1,
+        height: 50,
+        width: 50,
+    };
+    let clamped = clamp_update_region(&region, None).unwrap();
+    assert_eq!(clamped.left + clamped.width, DISPLAYWIDTH as u32);
+    assert_eq!(clamped.width, 1);
+}
+
+#[test]
+fn test_region_at_bottom_edge() {
+    let region = mxcfb_rect {
+        top: DISPLAYHEIGHT as u32 - 1,
+        left: 100,
+        height: 50,
+        width: 50,
+    };
+    let clamped = clamp_update_region(&region, None).unwrap();
+    assert_eq!(clamped.top + clamped.height, DISPLAYHEIGHT as u32);
+    assert_eq!(clamped.height, 1);
+}
+
+#[test]
+fn test_region_past_edges_is_dropped() {
+    let right = mxcfb_rect {
+        top: 0,
+        left: DISPLAYWIDTH as u32,
+        height: 50,
+        width: 50,
+    };
+    let bottom = mxcfb_rect {
+        top: DISPLAYHEIGHT as u32,
+        left: 0,
+        height: 50,
+        width: 50,
+    };
+    assert_eq!(clamp_update_region(&right, None), None);
+    assert_eq!(clamp_update_region(&bottom, None), None);
+}
+
+#[test]
+fn test_region_outside_viewport_is_dropped() {
+    let viewport = mxcfb_rect {
+        top: 0,
+        left: 0,
+        height: 100,
+        width: 100,
+    };
+    let region = mxcfb_rect {
+        top: 500,
+        left: 500,
+        height: 50,
+        width: 50,
+    };
+    assert_eq!(clamp_update_region(&region, Some(&viewport)), None);
+}
+
+#[test]
+fn test_difference_of_shorter_text() {
+    let old = mxcfb_rect {
+        top: 100,
+        left: 100,
+        height: 40,
+        width: 300,
+    };
+    let new = mxcfb_rect {
+        top: 100,
+        left: 100,
+        height: 40,
+        width: 120,
+    };
+    assert_eq!(
+        old.difference(&new),
+        vec![mxcfb_rect {
+            top: 100,
+            left: 220,
+            height: 40,
+            width: 180,
+        }]
+    );
+    assert!(new.difference(&old).is_empty());
+}
+
+#[test]
+fn test_difference_around_inner_rect() {
+    let outer = mxcfb_rect {
+        top: 0,
+        left: 0,
+        height: 10,
+        width: 10,
+    };
+    let inner = mxcfb_rect {
+        top: 2,
+        left: 3,
+        height: 4,
+        width: 5,
+    };
+    let parts = outer.difference(&inner);
+    assert_eq!(parts.len(), 4);
+    let area: u32 = parts.iter().map(|r| r.width * r.height).sum();
+    assert_eq!(area, 100 - 20);
+    assert!(parts.iter().all(|r| r.intersection(&inner).is_none()));
+}
+
+#[test]
+fn test_decompose_single_span_snaps_to_blocks() {
+    use libremarkable::framebuffer::refresh::decompose_spans;
+    assert_eq!(
+        decompose_spans(&[(3, 5, 20)]),
+        vec![mxcfb_rect {
+            top: 0,
+            left: 0,
+            height: 8,
+            width: 24,
+        }]
+    );
+    assert!(decompose_spans(&[(3, 20, 20), (4, 9, 2)]).is_empty());
+}
+
+#[test]
+fn test_decompose_merges_rows_with_the_same_runs() {
+    use libremarkable::framebuffer::refresh::decompose_spans;
+    let spans: Vec<(u32, u32, u32)> = (0..24).map(|y| (y, 16, 40)).collect();
+    assert_eq!(
+        decompose_spans(&spans),
+        vec![mxcfb_rect {
+            top: 0,
+            left: 16,
+            height: 24,
+            width: 24,
+        }]
+    );
+}
+
+#[test]
+fn test_decompose_covers_an_l_shape_with_two_rects() {
+    use libremarkable::framebuffer::refresh::decompose_spans;
+    // A full width bar over the first row of blocks and a narrow leg under its left end,
+    // with a gap of a row of blocks before a second separate bar
+    let mut spans: Vec<(u32, u32, u32)> = (0..8).map(|y| (y, 0, 32)).collect();
+    spans.extend((8..16).map(|y| (y, 0, 8)));
+    spans.push((30, 0, 32));
+    let rects = decompose_spans(&spans);
+    assert_eq!(
+        rects,
+        vec![
+            mxcfb_rect {
+                top: 0,
+                left: 0,
+                height: 8,
+                width: 32,
+            },
+            mxcfb_rect {
+                top: 8,
+                left: 0,
+                height: 8,
+                width: 8,
+            },
+            mxcfb_rect {
+                top: 24,
+                left: 0,
+                height: 8,
+                width: 32,
+            },
+        ]
+    );
+}